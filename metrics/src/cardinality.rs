@@ -0,0 +1,128 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+use crate::telemetry::OTHER_LABEL_VALUE;
+
+/// Maximum number of distinct values per label of a metric, 0 means unlimited
+#[derive(Clone, Debug, Default)]
+pub struct CardinalityLimits {
+    pub default_limit: usize,
+    /// Limits of single metrics by metric name, overriding the default limit
+    pub metric_limits: HashMap<String, usize>,
+}
+
+impl CardinalityLimits {
+    fn limit(&self, metric: &str) -> usize {
+        self.metric_limits.get(metric).copied().unwrap_or(self.default_limit)
+    }
+}
+
+/// Label values seen per label position of one metric
+type SeenValues = RwLock<Vec<HashSet<String>>>;
+
+/// Keeps track of the label values seen per label of a metric. Label values of the metrics
+/// derived from the log come from client input, once a label reached its limit new values
+/// are folded into one value so a misbehaving client can't create an unbounded number of series.
+#[derive(Clone, Debug)]
+pub struct CardinalityGuard {
+    limits: CardinalityLimits,
+    /// Label values seen by metric name, every metric is locked on its own
+    seen: Arc<RwLock<HashMap<String, Arc<SeenValues>>>>,
+}
+
+impl CardinalityGuard {
+    pub fn new(limits: CardinalityLimits) -> CardinalityGuard {
+        CardinalityGuard {
+            limits,
+            seen: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    fn seen_values(&self, metric: &str, labels: usize) -> Arc<SeenValues> {
+        if let Some(seen) = self.seen.read().unwrap().get(metric) {
+            return seen.clone();
+        }
+        self.seen.write().unwrap()
+            .entry(metric.to_string())
+            .or_insert_with(|| Arc::new(RwLock::new(vec![HashSet::new(); labels])))
+            .clone()
+    }
+
+    /// Returns for each label value whether it is allowed. Values seen before and the value
+    /// other values are folded into are always allowed, the latter without taking a slot.
+    pub fn check(&self, metric: &str, values: &[&str]) -> Vec<bool> {
+        let limit = self.limits.limit(metric);
+        if limit == 0 {
+            return vec![true; values.len()];
+        }
+
+        let seen = self.seen_values(metric, values.len());
+        let known = |seen_values: &HashSet<String>, value: &str| value == OTHER_LABEL_VALUE || seen_values.contains(value);
+
+        // most observations repeat known values, which only needs the read lock
+        if seen.read().unwrap().iter().zip(values).all(|(seen_values, value)| known(seen_values, value)) {
+            return vec![true; values.len()];
+        }
+
+        let mut labels = seen.write().unwrap();
+        labels.iter_mut().zip(values).map(|(seen_values, value)| {
+            if known(seen_values, value) {
+                true
+            } else if seen_values.len() < limit {
+                seen_values.insert(value.to_string());
+                true
+            } else {
+                false
+            }
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guard(default_limit: usize) -> CardinalityGuard {
+        CardinalityGuard::new(CardinalityLimits {
+            default_limit,
+            metric_limits: HashMap::from([(String::from("limited"), 1)]),
+        })
+    }
+
+    #[test]
+    fn allows_values_up_to_the_limit() {
+        let guard = guard(2);
+        assert_eq!(guard.check("metric", &["a", "x"]), vec![true, true]);
+        assert_eq!(guard.check("metric", &["b", "x"]), vec![true, true]);
+        assert_eq!(guard.check("metric", &["c", "y"]), vec![false, true]);
+        // values seen before stay allowed
+        assert_eq!(guard.check("metric", &["a", "y"]), vec![true, true]);
+    }
+
+    #[test]
+    fn limits_metrics_independently() {
+        let guard = guard(1);
+        assert_eq!(guard.check("first", &["a"]), vec![true]);
+        assert_eq!(guard.check("second", &["b"]), vec![true]);
+        assert_eq!(guard.check("first", &["b"]), vec![false]);
+    }
+
+    #[test]
+    fn metric_limit_overrides_default_limit() {
+        let guard = guard(0);
+        assert_eq!(guard.check("limited", &["a"]), vec![true]);
+        assert_eq!(guard.check("limited", &["b"]), vec![false]);
+        for value in ["a", "b", "c", "d"] {
+            assert_eq!(guard.check("unlimited", &[value]), vec![true]);
+        }
+    }
+
+    #[test]
+    fn other_value_takes_no_slot() {
+        let guard = guard(1);
+        assert_eq!(guard.check("metric", &[OTHER_LABEL_VALUE]), vec![true]);
+        assert_eq!(guard.check("metric", &["a"]), vec![true]);
+        assert_eq!(guard.check("metric", &[OTHER_LABEL_VALUE]), vec![true]);
+        assert_eq!(guard.check("metric", &["b"]), vec![false]);
+    }
+}
@@ -0,0 +1,229 @@
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Result;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::{fs, sync::watch, time};
+
+/// Position up to which a followed log file was processed
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileCheckpoint {
+    #[serde(rename = "inode")]
+    pub inode: u64,
+    #[serde(rename = "offset")]
+    pub offset: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct CheckpointFile {
+    #[serde(rename = "files")]
+    files: HashMap<String, FileCheckpoint>,
+}
+
+/// Lines of a followed file which were read but not yet processed by the pipeline. The
+/// checkpoint of the file is the start of the oldest of these lines, so a restart never
+/// skips a line which was still queued.
+#[derive(Debug)]
+pub struct FileProgress {
+    inode: u64,
+    state: Mutex<ProgressState>,
+}
+
+#[derive(Debug)]
+struct ProgressState {
+    /// Offset after the last line read
+    read_offset: u64,
+    /// Start offsets of the lines being processed by their end offset
+    pending: BTreeMap<u64, u64>,
+}
+
+impl FileProgress {
+    fn new(inode: u64, offset: u64) -> FileProgress {
+        FileProgress {
+            inode,
+            state: Mutex::new(ProgressState { read_offset: offset, pending: BTreeMap::new() }),
+        }
+    }
+
+    /// Records a line submitted to the pipeline, the returned ack is completed once it was processed
+    pub fn submitted(self: &Arc<Self>, start: u64, end: u64) -> LineAck {
+        let mut state = self.state.lock().unwrap();
+        state.read_offset = end;
+        state.pending.insert(end, start);
+        LineAck { progress: self.clone(), end }
+    }
+
+    /// Records a line which is not submitted on its own, e.g. a part of a split up line
+    pub fn skipped(&self, end: u64) {
+        self.state.lock().unwrap().read_offset = end;
+    }
+
+    pub fn checkpoint(&self) -> FileCheckpoint {
+        let state = self.state.lock().unwrap();
+        let offset = state.pending.values().next().copied().unwrap_or(state.read_offset);
+        FileCheckpoint { inode: self.inode, offset }
+    }
+}
+
+/// Marks a line read from a followed file as processed when completed
+#[derive(Debug)]
+pub struct LineAck {
+    progress: Arc<FileProgress>,
+    end: u64,
+}
+
+impl LineAck {
+    pub fn complete(self) {
+        self.progress.state.lock().unwrap().pending.remove(&self.end);
+    }
+}
+
+/// Read offsets of all followed log files, persisted to the checkpoint file
+pub struct Checkpoints {
+    path: String,
+    files: Mutex<HashMap<String, FileCheckpoint>>,
+    progress: Mutex<HashMap<String, Arc<FileProgress>>>,
+}
+
+impl Checkpoints {
+    /// Loads the checkpoints from the given file, a missing or invalid file yields no checkpoints
+    pub async fn load(path: &str) -> Checkpoints {
+        let files = match fs::read(path).await {
+            Ok(content) => match serde_json::from_slice::<CheckpointFile>(&content) {
+                Ok(checkpoint_file) => {
+                    info!("Loaded {} checkpoints from {}", checkpoint_file.files.len(), path);
+                    checkpoint_file.files
+                }
+                Err(e) => {
+                    warn!("Ignoring invalid checkpoint file {}: {}", path, e);
+                    HashMap::new()
+                }
+            },
+            Err(e) => {
+                info!("No checkpoints loaded from {} ({})", path, e);
+                HashMap::new()
+            }
+        };
+
+        Checkpoints {
+            path: path.to_string(),
+            files: Mutex::new(files),
+            progress: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, log_file: &str) -> Option<FileCheckpoint> {
+        self.files.lock().unwrap().get(log_file).copied()
+    }
+
+    /// Starts keeping track of the processed lines of a file read from the given offset,
+    /// replacing the progress of a file previously found at that path
+    pub fn track(&self, log_file: &str, inode: u64, offset: u64) -> Arc<FileProgress> {
+        let progress = Arc::new(FileProgress::new(inode, offset));
+        self.progress.lock().unwrap().insert(log_file.to_string(), progress.clone());
+        progress
+    }
+
+    /// Writes the checkpoints to disk, entries of files which no longer exist are dropped
+    pub async fn flush(&self) -> Result<()> {
+        let content = {
+            let mut files = self.files.lock().unwrap();
+            let mut progress = self.progress.lock().unwrap();
+            progress.retain(|log_file, _| Path::new(log_file).exists());
+            for (log_file, progress) in progress.iter() {
+                files.insert(log_file.clone(), progress.checkpoint());
+            }
+            files.retain(|log_file, _| Path::new(log_file).exists());
+            serde_json::to_vec(&CheckpointFile { files: files.clone() })?
+        };
+
+        // write to a temporary file first, so a crash never leaves a truncated checkpoint file
+        let tmp_path = format!("{}.tmp", self.path);
+        fs::write(&tmp_path, content).await?;
+        fs::rename(&tmp_path, &self.path).await?;
+        debug!("Checkpoints written to {}", self.path);
+        Ok(())
+    }
+}
+
+/// Flushes the checkpoints periodically. The last flush on termination is up to the caller,
+/// once the lines left in the pipeline were processed.
+pub async fn run_checkpoint_writer(checkpoints: &Checkpoints, interval: u64, mut termination_rx: watch::Receiver<()>) -> Result<()> {
+    let mut interval = time::interval(time::Duration::from_millis(interval));
+
+    loop {
+        tokio::select! {
+            biased;
+            _ = termination_rx.changed() => return Ok(()),
+
+            _ = interval.tick() => {
+                if let Err(e) = checkpoints.flush().await {
+                    warn!("Failed to write checkpoint file {}: {}", checkpoints.path, e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("metrics-checkpoint-{}-{}", std::process::id(), name));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn checkpoint_is_start_of_oldest_pending_line() {
+        let progress = Arc::new(FileProgress::new(7, 100));
+        assert_eq!(progress.checkpoint(), FileCheckpoint { inode: 7, offset: 100 });
+
+        let first = progress.submitted(100, 110);
+        let second = progress.submitted(110, 130);
+        progress.skipped(140);
+        assert_eq!(progress.checkpoint().offset, 100);
+
+        // lines can be processed out of order by multiple workers
+        second.complete();
+        assert_eq!(progress.checkpoint().offset, 100);
+        first.complete();
+        assert_eq!(progress.checkpoint().offset, 140);
+    }
+
+    #[tokio::test]
+    async fn flushes_and_loads_checkpoints() {
+        let path = temp_path("checkpoints.json");
+        let log_file = temp_path("hasura.log");
+        let removed_log_file = temp_path("removed.log");
+        std::fs::write(&log_file, "{}\n{}\n").unwrap();
+
+        let checkpoints = Checkpoints::load(&path).await;
+        assert_eq!(checkpoints.get(&log_file), None);
+        let progress = checkpoints.track(&log_file, 42, 0);
+        let ack = progress.submitted(0, 3);
+        progress.submitted(3, 6).complete();
+        checkpoints.track(&removed_log_file, 43, 0).skipped(3);
+        checkpoints.flush().await.unwrap();
+        assert_eq!(Checkpoints::load(&path).await.get(&log_file), Some(FileCheckpoint { inode: 42, offset: 0 }));
+
+        ack.complete();
+        checkpoints.flush().await.unwrap();
+        let loaded = Checkpoints::load(&path).await;
+        assert_eq!(loaded.get(&log_file), Some(FileCheckpoint { inode: 42, offset: 6 }));
+        // files which no longer exist are dropped
+        assert_eq!(loaded.get(&removed_log_file), None);
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&log_file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn ignores_invalid_checkpoint_file() {
+        let path = temp_path("invalid.json");
+        std::fs::write(&path, "not json").unwrap();
+        assert!(Checkpoints::load(&path).await.files.lock().unwrap().is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -144,7 +144,7 @@ async fn fetch_metadata(cfg: &Configuration, metric_obj: &Telemetry) -> Map<Stri
     
     let mut metadata = json!({}).as_object().unwrap().clone();
     
-    if cfg.disabled_collectors.contains(&crate::Collectors::EventTriggers) {
+    if cfg.disabled_collectors.contains(&crate::Collectors::EventTriggers) && cfg.disabled_collectors.contains(&crate::Collectors::RestEndpoints) {
         return metadata;
     }
     
@@ -8,6 +8,7 @@ mod metadata;
 mod scheduled_events;
 mod cron_triggers;
 mod event_triggers;
+mod rest_endpoints;
 
 pub(crate) async fn run_metadata_collector(cfg: &Configuration, metric_obj: &Telemetry, mut termination_rx: watch::Receiver<()>) -> std::io::Result<()> {
     let mut interval = time::interval(time::Duration::from_millis(cfg.collect_interval));
@@ -27,6 +28,7 @@ pub(crate) async fn run_metadata_collector(cfg: &Configuration, metric_obj: &Tel
                     async {
                         let metadata = metadata::check_metadata(cfg,metric_obj).await;
                         event_triggers::check_event_triggers(&cfg,metric_obj, &metadata).await;
+                        rest_endpoints::check_rest_endpoints(cfg,metric_obj, &metadata).await;
                     }
                 );
             },
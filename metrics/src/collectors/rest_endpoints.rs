@@ -0,0 +1,17 @@
+use crate::{Configuration, Telemetry};
+use log::{debug, info};
+use serde_json::{Map, Value};
+
+/// Updates the url templates of the RESTified endpoints used for the url label of the requests
+pub(crate) async fn check_rest_endpoints(cfg: &Configuration, metric_obj: &Telemetry, metadata: &Map<String, Value>) {
+    if cfg.disabled_collectors.contains(&crate::Collectors::RestEndpoints) {
+        info!("Not collecting rest endpoints.");
+        return;
+    }
+
+    // an empty metadata means it could not be fetched, the known endpoints are kept then
+    if let Some(metadata) = metadata.get("metadata").and_then(|m| m.as_object()) {
+        let count = metric_obj.url_normalizer.set_rest_endpoints(metadata);
+        debug!("Updated {} rest endpoint templates", count);
+    }
+}
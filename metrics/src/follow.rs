@@ -0,0 +1,77 @@
+use log::{debug, warn};
+use std::time::Duration;
+use tokio::time;
+
+/// How long to wait for a change notification before checking the file anyway, guards
+/// against missed events (e.g. on network file systems)
+#[cfg(target_os = "linux")]
+const NOTIFY_FALLBACK_INTERVAL: u64 = 10000;
+
+/// Waits for new content of a followed log file. On linux inotify is used to get notified
+/// about changes, otherwise (or if inotify is not available) the file is polled.
+pub enum FileWaiter {
+    #[cfg(target_os = "linux")]
+    Notify(Box<inotify::EventStream<[u8; 1024]>>),
+    Poll(Duration),
+}
+
+impl FileWaiter {
+    pub fn new(log_file: &str, sleep_time: u64) -> FileWaiter {
+        #[cfg(target_os = "linux")]
+        match Self::watch(log_file) {
+            Ok(stream) => {
+                debug!("Following hasura log file {} with inotify", log_file);
+                return FileWaiter::Notify(Box::new(stream));
+            }
+            Err(e) => {
+                warn!("Can't watch hasura log file {} with inotify, falling back to polling ({})", log_file, e);
+            }
+        }
+
+        debug!("Following hasura log file {} by polling", log_file);
+        FileWaiter::Poll(Duration::from_millis(sleep_time))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn watch(log_file: &str) -> std::io::Result<inotify::EventStream<[u8; 1024]>> {
+        use inotify::{Inotify, WatchMask};
+
+        let inotify = Inotify::init()?;
+        // the watch follows the inode, so moves and deletes of the followed file are reported
+        // as well, which lets the reader detect rotations right away
+        inotify.watches().add(
+            log_file,
+            WatchMask::MODIFY | WatchMask::ATTRIB | WatchMask::CLOSE_WRITE | WatchMask::MOVE_SELF | WatchMask::DELETE_SELF,
+        )?;
+        inotify.into_event_stream([0; 1024])
+    }
+
+    /// Name of the follow mode, used as metric label
+    pub fn mode(&self) -> &'static str {
+        match self {
+            #[cfg(target_os = "linux")]
+            FileWaiter::Notify(_) => "notify",
+            FileWaiter::Poll(_) => "poll",
+        }
+    }
+
+    pub async fn wait(&mut self) {
+        match self {
+            #[cfg(target_os = "linux")]
+            FileWaiter::Notify(stream) => {
+                use futures::StreamExt;
+
+                tokio::select! {
+                    event = stream.next() => {
+                        if let Some(Err(e)) = event {
+                            warn!("Failed to read inotify event: {}", e);
+                            time::sleep(Duration::from_millis(NOTIFY_FALLBACK_INTERVAL)).await;
+                        }
+                    }
+                    _ = time::sleep(Duration::from_millis(NOTIFY_FALLBACK_INTERVAL)) => (),
+                }
+            }
+            FileWaiter::Poll(sleep_time) => time::sleep(*sleep_time).await,
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use graphql_parser::query::{parse_query, Definition, Document, OperationDefinition, Selection, SelectionSet};
+use std::collections::HashSet;
+
+/// Operation type and root fields of the executed operation of a graphql request
+pub struct OperationInfo {
+    pub operation_type: &'static str,
+    pub root_fields: Vec<String>,
+}
+
+/// Parses the query text of a request and returns the type and root fields of the operation
+/// that was executed, which is the one named by the operation name or the only one in the
+/// document. Returns None if the query can't be parsed or the operation is not found.
+pub fn parse_operation(query: &str, operation_name: Option<&str>) -> Option<OperationInfo> {
+    let document = parse_query::<&str>(query).ok()?;
+
+    let mut operations = document.definitions.iter().filter_map(|definition| match definition {
+        Definition::Operation(operation) => Some(operation),
+        Definition::Fragment(_) => None,
+    });
+    let operation = match operation_name.filter(|name| !name.is_empty()) {
+        Some(name) => operations.find(|operation| self::operation_name(operation) == Some(name))?,
+        None => {
+            let operation = operations.next()?;
+            if operations.next().is_some() {
+                return None;
+            }
+            operation
+        }
+    };
+
+    let (operation_type, selection_set) = match operation {
+        OperationDefinition::SelectionSet(selection_set) => ("query", selection_set),
+        OperationDefinition::Query(query) => ("query", &query.selection_set),
+        OperationDefinition::Mutation(mutation) => ("mutation", &mutation.selection_set),
+        OperationDefinition::Subscription(subscription) => ("subscription", &subscription.selection_set),
+    };
+
+    let mut root_fields = Vec::new();
+    collect_root_fields(&document, selection_set, &mut HashSet::new(), &mut root_fields);
+    root_fields.sort();
+    root_fields.dedup();
+
+    Some(OperationInfo { operation_type, root_fields })
+}
+
+fn operation_name<'a>(operation: &OperationDefinition<'a, &'a str>) -> Option<&'a str> {
+    match operation {
+        OperationDefinition::SelectionSet(_) => None,
+        OperationDefinition::Query(query) => query.name,
+        OperationDefinition::Mutation(mutation) => mutation.name,
+        OperationDefinition::Subscription(subscription) => subscription.name,
+    }
+}
+
+/// Collects the names (not the aliases) of the fields of the root selection set, following
+/// fragments spread into it. Every fragment is followed only once to guard against cycles.
+fn collect_root_fields<'a>(
+    document: &Document<'a, &'a str>,
+    selection_set: &SelectionSet<'a, &'a str>,
+    visited: &mut HashSet<&'a str>,
+    root_fields: &mut Vec<String>,
+) {
+    for selection in &selection_set.items {
+        match selection {
+            Selection::Field(field) => root_fields.push(field.name.to_string()),
+            Selection::InlineFragment(fragment) => collect_root_fields(document, &fragment.selection_set, visited, root_fields),
+            Selection::FragmentSpread(spread) => {
+                if !visited.insert(spread.fragment_name) {
+                    continue;
+                }
+                let fragment = document.definitions.iter().find_map(|definition| match definition {
+                    Definition::Fragment(fragment) if fragment.name == spread.fragment_name => Some(fragment),
+                    _ => None,
+                });
+                if let Some(fragment) = fragment {
+                    collect_root_fields(document, &fragment.selection_set, visited, root_fields);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(query: &str, operation_name: Option<&str>) -> Option<(&'static str, Vec<String>)> {
+        parse_operation(query, operation_name).map(|info| (info.operation_type, info.root_fields))
+    }
+
+    #[test]
+    fn parses_anonymous_operations() {
+        assert_eq!(parse("{ users { id } posts { id } }", None), Some(("query", vec!["posts".to_string(), "users".to_string()])));
+        assert_eq!(parse("query { users { id } }", Some("")), Some(("query", vec!["users".to_string()])));
+    }
+
+    #[test]
+    fn reports_operation_types() {
+        assert_eq!(parse("mutation Add { insert_users(objects: []) { affected_rows } }", None), Some(("mutation", vec!["insert_users".to_string()])));
+        assert_eq!(parse("subscription { users { id } }", None), Some(("subscription", vec!["users".to_string()])));
+    }
+
+    #[test]
+    fn selects_operation_by_name() {
+        let query = "query A { users { id } } mutation B { delete_users { affected_rows } }";
+        assert_eq!(parse(query, Some("B")), Some(("mutation", vec!["delete_users".to_string()])));
+        assert_eq!(parse(query, Some("C")), None);
+        // the operation to execute is ambiguous without a name
+        assert_eq!(parse(query, None), None);
+    }
+
+    #[test]
+    fn uses_field_names_instead_of_aliases() {
+        assert_eq!(parse("{ a: users { id } b: users { name } }", None), Some(("query", vec!["users".to_string()])));
+    }
+
+    #[test]
+    fn follows_fragments() {
+        let query = "
+            query Q { ...Root ... on query_root { posts { id } } }
+            fragment Root on query_root { users { id } ...Other }
+            fragment Other on query_root { comments { id } ...Root }
+        ";
+        assert_eq!(parse(query, None), Some(("query", vec!["comments".to_string(), "posts".to_string(), "users".to_string()])));
+        assert_eq!(parse("{ ...Missing }", None), Some(("query", vec![])));
+    }
+
+    #[test]
+    fn rejects_invalid_queries() {
+        assert_eq!(parse("{ users { id }", None), None);
+        assert_eq!(parse("fragment F on query_root { users { id } }", None), None);
+    }
+}
@@ -0,0 +1,274 @@
+use actix_web::{http::header, post, web, HttpRequest, HttpResponse, Responder};
+use futures::StreamExt;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::from_slice;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+use crate::inputformat::{InputFormat, LineDecoder};
+use crate::pipeline::Pipeline;
+use crate::Telemetry;
+
+/// Source label value of lines received via the ingest endpoint
+const INGEST_SOURCE: &str = "ingest";
+
+/// Settings of the ingest endpoint
+pub(crate) struct IngestConfig {
+    pub max_body_size: usize,
+    /// Bearer token the requests must carry, any request is accepted without a token
+    pub token: Option<String>,
+    pub log_format: InputFormat,
+    pub pipeline: Pipeline,
+    pub termination_rx: watch::Receiver<()>,
+}
+
+/// Body of a Loki push API request (`application/json` flavour)
+#[derive(Deserialize)]
+struct LokiPushRequest {
+    #[serde(rename = "streams")]
+    streams: Vec<LokiStream>,
+}
+
+#[derive(Deserialize)]
+struct LokiStream {
+    /// `[<unix epoch in nanoseconds>, <log line>, <optional structured metadata>]`
+    #[serde(rename = "values")]
+    values: Vec<Vec<serde_json::Value>>,
+}
+
+#[derive(Serialize)]
+struct IngestResponse {
+    accepted: u64,
+    rejected: u64,
+}
+
+struct IngestResult {
+    accepted: u64,
+    rejected: u64,
+    source: Arc<str>,
+    decoder: LineDecoder,
+}
+
+impl IngestResult {
+    fn new(log_format: InputFormat) -> IngestResult {
+        IngestResult {
+            accepted: 0,
+            rejected: 0,
+            source: Arc::from(INGEST_SOURCE),
+            decoder: LineDecoder::new(log_format),
+        }
+    }
+
+    /// Queues the line for the workers, returns false if the adapter terminated meanwhile
+    async fn submit(&mut self, line: &str, ingest_cfg: &IngestConfig) -> bool {
+        if line.trim().is_empty() {
+            return true;
+        }
+
+        if let Some(line) = self.decoder.decode(line) {
+            // the submit blocks on a full queue, which is not drained anymore once terminated
+            let mut termination_rx = ingest_cfg.termination_rx.clone();
+            tokio::select! {
+                biased;
+                _ = termination_rx.changed() => return false,
+                _ = ingest_cfg.pipeline.submit(line.into_owned(), &self.source) => (),
+            }
+            self.accepted += 1;
+        }
+        true
+    }
+}
+
+/// Compares the bearer token of the request with the configured token, in constant time
+fn authorized(req: &HttpRequest, token: &str) -> bool {
+    let bearer = req.headers().get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match bearer {
+        Some(bearer) if bearer.len() == token.len() => {
+            bearer.bytes().zip(token.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+        }
+        _ => false,
+    }
+}
+
+/// Accepts hasura log lines as newline delimited JSON or as a Loki push API JSON body.
+/// The lines are queued for the workers like the lines of any other log source.
+#[post("/ingest")]
+pub(crate) async fn ingest(req: HttpRequest, mut payload: web::Payload, ingest_cfg: web::Data<IngestConfig>, metric_obj: web::Data<Telemetry>) -> impl Responder {
+    if let Some(token) = &ingest_cfg.token {
+        if !authorized(&req, token) {
+            metric_obj.INGEST_REQUESTS.with_label_values(&["rejected"]).inc();
+            return HttpResponse::Unauthorized()
+                .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+                .body("missing or invalid bearer token");
+        }
+    }
+
+    // the body is read here instead of by the extractor, so oversized pushes are counted as rejected
+    let mut body = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                metric_obj.INGEST_REQUESTS.with_label_values(&["rejected"]).inc();
+                return HttpResponse::BadRequest().body(format!("failed to read request body: {}", e));
+            }
+        };
+        if body.len() + chunk.len() > ingest_cfg.max_body_size {
+            metric_obj.INGEST_REQUESTS.with_label_values(&["rejected"]).inc();
+            return HttpResponse::PayloadTooLarge().body("request body exceeds the maximum size");
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    let mut result = IngestResult::new(ingest_cfg.log_format);
+    let mut terminated = false;
+
+    match from_slice::<LokiPushRequest>(&body) {
+        Ok(push) => {
+            debug!("Ingesting Loki push request with {} streams", push.streams.len());
+            for value in push.streams.iter().flat_map(|s| s.values.iter()) {
+                let line = match value.get(1).and_then(|v| v.as_str()) {
+                    Some(line) => line,
+                    None => {
+                        result.rejected += 1;
+                        continue;
+                    }
+                };
+                if !result.submit(line, &ingest_cfg).await {
+                    terminated = true;
+                    break;
+                }
+            }
+        }
+        Err(_) => {
+            match std::str::from_utf8(&body) {
+                Ok(text) => {
+                    for line in text.lines() {
+                        if !result.submit(line, &ingest_cfg).await {
+                            terminated = true;
+                            break;
+                        }
+                    }
+                }
+                Err(_) => {
+                    metric_obj.INGEST_REQUESTS.with_label_values(&["rejected"]).inc();
+                    return HttpResponse::BadRequest().body("request body is not valid UTF-8");
+                }
+            }
+        }
+    }
+
+    metric_obj.INGEST_LINES.with_label_values(&["accepted"]).inc_by(result.accepted);
+    metric_obj.INGEST_LINES.with_label_values(&["rejected"]).inc_by(result.rejected);
+    if terminated {
+        metric_obj.INGEST_REQUESTS.with_label_values(&["rejected"]).inc();
+        return HttpResponse::ServiceUnavailable().body("the adapter is terminating");
+    }
+    metric_obj.INGEST_REQUESTS.with_label_values(&["accepted"]).inc();
+
+    HttpResponse::Ok().json(IngestResponse {
+        accepted: result.accepted,
+        rejected: result.rejected,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, App};
+    use serde_json::Value;
+
+    use crate::logprocessor::ProcessorConfig;
+    use crate::pipeline::{self, OverflowPolicy};
+    use crate::telemetry::tests::telemetry;
+
+    const STARTUP_LINE: &str = r#"{"type":"startup","timestamp":"2024-01-01T00:00:00.000+0000","level":"info","detail":{"kind":"server_configuration","info":{"port":8080}}}"#;
+
+    fn ingest_config(pipeline: &Pipeline, token: Option<&str>, log_format: InputFormat, termination_rx: watch::Receiver<()>) -> web::Data<IngestConfig> {
+        web::Data::new(IngestConfig {
+            max_body_size: 1024,
+            token: token.map(String::from),
+            log_format,
+            pipeline: pipeline.clone(),
+            termination_rx,
+        })
+    }
+
+    /// Posts the body to the ingest endpoint, returns the status and the response body
+    async fn post(ingest_cfg: web::Data<IngestConfig>, authorization: Option<&str>, body: String) -> (StatusCode, String) {
+        let app = test::init_service(App::new()
+            .app_data(web::Data::new(telemetry()))
+            .app_data(ingest_cfg)
+            .service(ingest)).await;
+        let mut req = test::TestRequest::post().uri("/ingest").set_payload(body);
+        if let Some(authorization) = authorization {
+            req = req.insert_header((header::AUTHORIZATION, authorization));
+        }
+        let resp = test::call_service(&app, req.to_request()).await;
+        let status = resp.status();
+        (status, String::from_utf8(test::read_body(resp).await.to_vec()).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn queues_pushed_lines() {
+        let metric_obj = telemetry();
+        let pipeline = Pipeline::new(100, OverflowPolicy::Block, ProcessorConfig::default(), &metric_obj);
+        let (termination_tx, termination_rx) = watch::channel(());
+        let lines = || metric_obj.LOG_LINES_COUNTER.with_label_values(&["startup", INGEST_SOURCE]).get();
+        let before = lines();
+
+        let ingest_cfg = ingest_config(&pipeline, None, InputFormat::Hasura, termination_rx.clone());
+        let (status, body) = post(ingest_cfg.clone(), None, format!("{}\n\n{}\n", STARTUP_LINE, STARTUP_LINE)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap(), serde_json::json!({"accepted": 2, "rejected": 0}));
+
+        let push = serde_json::json!({"streams": [{"stream": {"app": "hasura"}, "values": [["1704067200000000000", STARTUP_LINE], ["1704067200000000000"]]}]});
+        let (status, body) = post(ingest_cfg, None, push.to_string()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap(), serde_json::json!({"accepted": 1, "rejected": 1}));
+
+        // the lines are unwrapped according to the log format, split lines are joined within a request
+        let ingest_cfg = ingest_config(&pipeline, None, InputFormat::Cri, termination_rx.clone());
+        let (head, tail) = STARTUP_LINE.split_at(20);
+        let cri = format!("2024-01-01T00:00:00.000000000Z stdout P {}\n2024-01-01T00:00:00.000000000Z stdout F {}\n", head, tail);
+        let (status, body) = post(ingest_cfg, None, cri).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap(), serde_json::json!({"accepted": 1, "rejected": 0}));
+
+        // nothing is processed before the workers took the lines from the queue
+        assert_eq!(lines(), before);
+        termination_tx.send(()).unwrap();
+        pipeline::run_workers(&pipeline, 1, termination_rx).await.unwrap();
+        assert_eq!(lines(), before + 4);
+    }
+
+    #[actix_web::test]
+    async fn requires_the_configured_token() {
+        let metric_obj = telemetry();
+        let pipeline = Pipeline::new(100, OverflowPolicy::Block, ProcessorConfig::default(), &metric_obj);
+        let (_termination_tx, termination_rx) = watch::channel(());
+        let ingest_cfg = ingest_config(&pipeline, Some("secret"), InputFormat::Hasura, termination_rx);
+
+        for authorization in [None, Some("Bearer other"), Some("Bearer secre"), Some("secret"), Some("Basic secret")] {
+            let (status, _) = post(ingest_cfg.clone(), authorization, STARTUP_LINE.to_string()).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{:?}", authorization);
+        }
+        let (status, _) = post(ingest_cfg, Some("Bearer secret"), STARTUP_LINE.to_string()).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn rejects_lines_on_termination() {
+        let metric_obj = telemetry();
+        let pipeline = Pipeline::new(1, OverflowPolicy::Block, ProcessorConfig::default(), &metric_obj);
+        let (termination_tx, termination_rx) = watch::channel(());
+        termination_tx.send(()).unwrap();
+        let ingest_cfg = ingest_config(&pipeline, None, InputFormat::Hasura, termination_rx);
+
+        let (status, _) = post(ingest_cfg, None, format!("{}\n{}\n", STARTUP_LINE, STARTUP_LINE)).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,245 @@
+use log::warn;
+use serde::Deserialize;
+use serde_json::from_str;
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Reassembled lines larger than this are dropped to keep the memory usage bounded
+const MAX_PARTIAL_SIZE: usize = 4 * 1024 * 1024;
+
+/// Format of the lines read from a log file
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputFormat {
+    /// Plain hasura JSON log lines
+    Hasura,
+    /// Docker json-file logging driver, `{"log":"...","stream":"stdout","time":"..."}`
+    Docker,
+    /// Kubernetes CRI container logs, `<time> <stream> <P|F> <message>`
+    Cri,
+}
+
+#[derive(Deserialize)]
+struct DockerLogLine {
+    #[serde(rename = "log")]
+    log: String,
+    #[serde(rename = "stream", default)]
+    stream: String,
+}
+
+/// The parts of a split up line read so far
+#[derive(Default)]
+struct Partial {
+    line: String,
+    /// Set once the line got too large, the remaining parts of the line are dropped
+    discarding: bool,
+}
+
+impl Partial {
+    fn append(&mut self, message: &str) {
+        if self.discarding {
+            return;
+        }
+        if self.line.len() + message.len() > MAX_PARTIAL_SIZE {
+            warn!("Dropping split log line larger than {} bytes", MAX_PARTIAL_SIZE);
+            self.line = String::new();
+            self.discarding = true;
+            return;
+        }
+        self.line.push_str(message);
+    }
+
+    /// The reassembled line ending with the given last part, None if the line was dropped
+    fn complete<'a>(&mut self, message: &'a str) -> Option<Cow<'a, str>> {
+        if std::mem::take(&mut self.discarding) {
+            return None;
+        }
+        if self.line.is_empty() {
+            return Some(Cow::Borrowed(message));
+        }
+        let mut line = std::mem::take(&mut self.line);
+        line.push_str(message);
+        Some(Cow::Owned(line))
+    }
+}
+
+/// Unwraps the container runtime envelope of log lines and reassembles lines which the
+/// container runtime split up. One decoder is used per followed file, stdout and stderr
+/// are interleaved in the file so the parts are collected per stream.
+pub struct LineDecoder {
+    format: InputFormat,
+    partials: HashMap<String, Partial>,
+}
+
+impl LineDecoder {
+    pub fn new(format: InputFormat) -> LineDecoder {
+        LineDecoder {
+            format,
+            partials: HashMap::new(),
+        }
+    }
+
+    fn partial(&mut self, stream: &str) -> &mut Partial {
+        if !self.partials.contains_key(stream) {
+            self.partials.insert(stream.to_string(), Partial::default());
+        }
+        self.partials.get_mut(stream).unwrap()
+    }
+
+    /// Returns the hasura log line held by the given line, None if the line is only a part
+    /// of a split up line or can't be decoded
+    pub fn decode<'a>(&mut self, line: &'a str) -> Option<Cow<'a, str>> {
+        match self.format {
+            InputFormat::Hasura => Some(Cow::Borrowed(line)),
+            InputFormat::Docker => self.decode_docker(line),
+            InputFormat::Cri => self.decode_cri(line),
+        }
+    }
+
+    fn decode_docker<'a>(&mut self, line: &str) -> Option<Cow<'a, str>> {
+        match from_str::<DockerLogLine>(line) {
+            // docker splits long lines into multiple entries, only the last one ends with a new line
+            Ok(entry) => match entry.log.strip_suffix('\n') {
+                Some(message) => self.partial(&entry.stream).complete(message).map(|line| Cow::Owned(line.into_owned())),
+                None => {
+                    self.partial(&entry.stream).append(&entry.log);
+                    None
+                }
+            },
+            Err(e) => {
+                warn!("Failed to parse docker log line: {}", e);
+                None
+            }
+        }
+    }
+
+    fn decode_cri<'a>(&mut self, line: &'a str) -> Option<Cow<'a, str>> {
+        let mut fields = line.splitn(4, ' ');
+        let (_time, stream, tag) = (fields.next(), fields.next().unwrap_or(""), fields.next());
+        let message = fields.next().unwrap_or("");
+
+        match tag {
+            // the tag may carry more flags separated by ':', the first one is the partial flag
+            Some(tag) if tag.starts_with('P') => {
+                self.partial(stream).append(message);
+                None
+            }
+            Some(tag) if tag.starts_with('F') => self.partial(stream).complete(message),
+            _ => {
+                warn!("Failed to parse CRI log line");
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(format: InputFormat, lines: &[&str]) -> Vec<String> {
+        let mut decoder = LineDecoder::new(format);
+        lines.iter().filter_map(|line| decoder.decode(line).map(Cow::into_owned)).collect()
+    }
+
+    #[test]
+    fn passes_hasura_lines_through() {
+        assert_eq!(decode_all(InputFormat::Hasura, &["{\"level\":\"info\"}"]), vec!["{\"level\":\"info\"}"]);
+    }
+
+    #[test]
+    fn decodes_docker_lines() {
+        let lines = [
+            r#"{"log":"{\"level\":\"info\"}\n","stream":"stdout","time":"2024-01-01T00:00:00.000000000Z"}"#,
+            "not json",
+            r#"{"stream":"stdout"}"#,
+        ];
+        assert_eq!(decode_all(InputFormat::Docker, &lines), vec!["{\"level\":\"info\"}"]);
+    }
+
+    #[test]
+    fn joins_split_docker_lines() {
+        let lines = [
+            r#"{"log":"{\"level\":","stream":"stdout","time":"2024-01-01T00:00:00.000000000Z"}"#,
+            r#"{"log":"\"info\"","stream":"stdout","time":"2024-01-01T00:00:00.000000000Z"}"#,
+            r#"{"log":"}\n","stream":"stdout","time":"2024-01-01T00:00:00.000000000Z"}"#,
+            r#"{"log":"{}\n","stream":"stdout","time":"2024-01-01T00:00:00.000000000Z"}"#,
+        ];
+        assert_eq!(decode_all(InputFormat::Docker, &lines), vec!["{\"level\":\"info\"}", "{}"]);
+    }
+
+    #[test]
+    fn decodes_cri_lines() {
+        let lines = [
+            "2024-01-01T00:00:00.000000000Z stdout F {\"level\":\"info\"}",
+            "2024-01-01T00:00:00.000000000Z stderr F:x {}",
+            "2024-01-01T00:00:00.000000000Z stdout F",
+            "2024-01-01T00:00:00.000000000Z stdout X {}",
+            "garbage",
+        ];
+        assert_eq!(decode_all(InputFormat::Cri, &lines), vec!["{\"level\":\"info\"}", "{}", ""]);
+    }
+
+    #[test]
+    fn joins_partial_cri_lines() {
+        let lines = [
+            "2024-01-01T00:00:00.000000000Z stdout P {\"level\":",
+            "2024-01-01T00:00:00.000000000Z stdout P \"info\", \"a\": 1",
+            "2024-01-01T00:00:00.000000000Z stdout F }",
+            "2024-01-01T00:00:00.000000000Z stdout F {}",
+        ];
+        assert_eq!(decode_all(InputFormat::Cri, &lines), vec!["{\"level\":\"info\", \"a\": 1}", "{}"]);
+    }
+
+    #[test]
+    fn joins_partial_cri_lines_per_stream() {
+        let lines = [
+            "2024-01-01T00:00:00.000000000Z stdout P {\"level\":",
+            "2024-01-01T00:00:00.000000000Z stderr P {\"level\":",
+            "2024-01-01T00:00:00.000000000Z stderr F \"error\"}",
+            "2024-01-01T00:00:00.000000000Z stdout F \"info\"}",
+        ];
+        assert_eq!(decode_all(InputFormat::Cri, &lines), vec!["{\"level\":\"error\"}", "{\"level\":\"info\"}"]);
+    }
+
+    #[test]
+    fn joins_split_docker_lines_per_stream() {
+        let lines = [
+            r#"{"log":"{\"level\":","stream":"stdout","time":"2024-01-01T00:00:00.000000000Z"}"#,
+            r#"{"log":"{\"level\":","stream":"stderr","time":"2024-01-01T00:00:00.000000000Z"}"#,
+            r#"{"log":"\"info\"}\n","stream":"stdout","time":"2024-01-01T00:00:00.000000000Z"}"#,
+            r#"{"log":"\"error\"}\n","stream":"stderr","time":"2024-01-01T00:00:00.000000000Z"}"#,
+        ];
+        assert_eq!(decode_all(InputFormat::Docker, &lines), vec!["{\"level\":\"info\"}", "{\"level\":\"error\"}"]);
+    }
+
+    #[test]
+    fn drops_oversized_partial_lines() {
+        let chunk = "x".repeat(MAX_PARTIAL_SIZE / 2 + 1);
+        let first = format!("2024-01-01T00:00:00.000000000Z stdout P {}", chunk);
+        let mut decoder = LineDecoder::new(InputFormat::Cri);
+        assert!(decoder.decode(&first).is_none());
+        assert!(decoder.decode(&first).is_none());
+        assert!(decoder.partials["stdout"].line.is_empty());
+
+        // the remaining parts of the dropped line are dropped up to its last part
+        assert!(decoder.decode("2024-01-01T00:00:00.000000000Z stdout P {}").is_none());
+        assert!(decoder.decode("2024-01-01T00:00:00.000000000Z stdout F }").is_none());
+        assert!(decoder.partials["stdout"].line.is_empty());
+        assert_eq!(decoder.decode("2024-01-01T00:00:00.000000000Z stdout F {}").as_deref(), Some("{}"));
+    }
+
+    #[test]
+    fn drops_oversized_split_docker_lines() {
+        let chunk = "x".repeat(MAX_PARTIAL_SIZE / 2 + 1);
+        let first = format!(r#"{{"log":"{}","stream":"stdout","time":"2024-01-01T00:00:00.000000000Z"}}"#, chunk);
+        let lines = [
+            first.as_str(),
+            first.as_str(),
+            r#"{"log":"{\"level\":\"error\"}\n","stream":"stderr","time":"2024-01-01T00:00:00.000000000Z"}"#,
+            r#"{"log":"xx","stream":"stdout","time":"2024-01-01T00:00:00.000000000Z"}"#,
+            r#"{"log":"x\n","stream":"stdout","time":"2024-01-01T00:00:00.000000000Z"}"#,
+            r#"{"log":"{}\n","stream":"stdout","time":"2024-01-01T00:00:00.000000000Z"}"#,
+        ];
+        assert_eq!(decode_all(InputFormat::Docker, &lines), vec!["{\"level\":\"error\"}", "{}"]);
+    }
+}
@@ -1,19 +1,85 @@
+use futures::{future::try_join_all, stream::FuturesUnordered, StreamExt};
 use log::{debug, error, info, warn};
+use prometheus::IntGauge;
+use std::collections::HashSet;
+use std::os::unix::fs::FileExt;
 use std::os::unix::prelude::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tokio::{
     fs::File,
-    io::{AsyncBufReadExt, BufReader},
-    sync::watch,
+    io::{self, AsyncBufReadExt, AsyncSeekExt, BufReader, SeekFrom},
+    sync::{mpsc, watch},
     time,
 };
-use std::io::Result;
+use flate2::read::GzDecoder;
+use std::fs::Metadata;
+use std::io::{BufRead, Read, Result};
 use std::time::Duration;
 
 
-use crate::{logprocessor, Telemetry};
+use crate::checkpoint::Checkpoints;
+use crate::follow::FileWaiter;
+use crate::inputformat::LineDecoder;
+use crate::pipeline::Pipeline;
+use crate::{Configuration, Telemetry};
 
+/// Where to start reading a log file which already exists when the adapter starts
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StartPosition {
+    Beginning,
+    End,
+    Checkpoint,
+}
+
+/// Log file name which makes the adapter read the hasura log from its own stdin
+pub const STDIN_LOG_FILE: &str = "-";
+
+/// Source label value of lines read from stdin
+const STDIN_SOURCE: &str = "stdin";
+
+/// Interval in which glob patterns are evaluated again to discover new log files
+const GLOB_DISCOVERY_INTERVAL: u64 = 5000;
+
+/// Number of bytes at the beginning of a followed file which are compared to detect a truncation
+const FINGERPRINT_SIZE: u64 = 4096;
+
+/// Number of lines of a rotated copy which are read ahead of the pipeline
+const ROTATED_LINES_BUFFER: usize = 1024;
+
+/// State shared by the readers of all log files
+pub(crate) struct ReaderContext<'a> {
+    pub cfg: &'a Configuration,
+    pub metric_obj: &'a Telemetry,
+    pub checkpoints: Option<&'a Checkpoints>,
+    pub pipeline: &'a Pipeline,
+}
+
+pub(crate) async fn read_files(ctx: &ReaderContext<'_>, termination_tx: watch::Sender<()>, termination_rx: watch::Receiver<()>) -> Result<()> {
+    let log_files = &ctx.cfg.log_files;
+    if log_files.iter().any(|f| f == STDIN_LOG_FILE) {
+        if log_files.len() > 1 {
+            warn!("Reading hasura log from stdin, ignoring the other log files");
+        }
+        return read_stdin(ctx, termination_tx, termination_rx).await;
+    }
+
+    try_join_all(log_files.iter().map(|log_file| {
+        if is_glob(log_file) {
+            futures::future::Either::Left(follow_glob(log_file, ctx, termination_rx.clone()))
+        } else {
+            futures::future::Either::Right(read_file(log_file, ctx, termination_rx.clone()))
+        }
+    })).await?;
+
+    Ok(())
+}
+
+pub(crate) async fn read_file(log_file: &str, ctx: &ReaderContext<'_>, mut termination_rx: watch::Receiver<()>) -> Result<()> {
+    // the configured start position only applies to the file as found on startup,
+    // once it was recreated it's a new file that has to be read completely
+    let mut start_position = ctx.cfg.start_position;
 
-pub async fn read_file(log_file: &str, metric_obj: &Telemetry, sleep_time: u64, mut termination_rx: watch::Receiver<()>) -> Result<()> {
     loop {
         tokio::select! {
             biased;
@@ -28,7 +94,9 @@ pub async fn read_file(log_file: &str, metric_obj: &Telemetry, sleep_time: u64,
                 match result {
                     Ok(file) => {
                         info!("Hasura log file {} open, will follow the log", log_file);
-                        match process_file(metric_obj, &file, sleep_time, termination_rx.clone()).await {
+                        let result = process_file(log_file, &file, start_position, ctx, termination_rx.clone()).await;
+                        start_position = StartPosition::Beginning;
+                        match result {
                             Ok(true) => (),
                             Ok(false) => return Ok(()),
                             Err(e) => {
@@ -52,31 +120,569 @@ pub async fn read_file(log_file: &str, metric_obj: &Telemetry, sleep_time: u64,
     }
 }
 
-async fn process_file(metric_obj: &Telemetry, file: &File, sleep_time: u64, mut termination_rx: watch::Receiver<()>) -> Result<bool> {
-    let reader = BufReader::new(file.try_clone().await?);
-    let mut lines = reader.lines();
+/// Follows all files matching the glob pattern. The pattern is evaluated periodically, so
+/// files created later on are picked up as well. A file that was removed is followed again
+/// once it shows up in the pattern again.
+async fn follow_glob(pattern: &str, ctx: &ReaderContext<'_>, mut termination_rx: watch::Receiver<()>) -> Result<()> {
+    // files matched on startup are read from the configured start position, files showing up later are new
+    let mut start_position = ctx.cfg.start_position;
+    let mut followed: HashSet<PathBuf> = HashSet::new();
+    let mut followers = FuturesUnordered::new();
+    let mut discovery = time::interval(Duration::from_millis(GLOB_DISCOVERY_INTERVAL));
 
     loop {
         tokio::select! {
             biased;
-            _ = termination_rx.changed() => return Ok(false),
+            _ = termination_rx.changed() => return Ok(()),
+
+            Some(path) = followers.next() => {
+                followed.remove(&path);
+            }
+
+            _ = discovery.tick() => {
+                for path in discover_files(pattern) {
+                    if followed.insert(path.clone()) {
+                        followers.push(follow_discovered_file(path, start_position, ctx, termination_rx.clone()));
+                    }
+                }
+                start_position = StartPosition::Beginning;
+            }
+        }
+    }
+}
+
+/// Follows a file found via a glob pattern until it is removed, returns the path of the file
+async fn follow_discovered_file(path: PathBuf, start_position: StartPosition, ctx: &ReaderContext<'_>, termination_rx: watch::Receiver<()>) -> PathBuf {
+    let log_file = path.to_string_lossy();
+    match File::open(&path).await {
+        Ok(file) => {
+            info!("Hasura log file {} open, will follow the log", path.display());
+            if let Err(e) = process_file(&log_file, &file, start_position, ctx, termination_rx).await {
+                warn!("Error reading logfile {}: {}", path.display(), e);
+            }
+        }
+        Err(e) => {
+            error!("File {} could not be opened ({})", path.display(), e);
+        }
+    }
+    path
+}
+
+fn discover_files(pattern: &str) -> Vec<PathBuf> {
+    match glob::glob(pattern) {
+        Ok(paths) => paths
+            .filter_map(|entry| entry.map_err(|e| warn!("Failed to read glob entry: {}", e)).ok())
+            .filter(|path| !path.is_dir())
+            .collect(),
+        Err(e) => {
+            error!("Invalid log file pattern {}: {}", pattern, e);
+            vec![]
+        }
+    }
+}
+
+fn is_glob(log_file: &str) -> bool {
+    log_file.contains(['*', '?', '['])
+}
+
+/// Source label value for a log file, the file name without extension
+fn source_name(path: &Path) -> String {
+    path.file_stem()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+async fn read_stdin(ctx: &ReaderContext<'_>, termination_tx: watch::Sender<()>, mut termination_rx: watch::Receiver<()>) -> Result<()> {
+    info!("Reading hasura log from stdin");
+    let mut lines = BufReader::new(io::stdin()).lines();
+    let source: Arc<str> = Arc::from(STDIN_SOURCE);
+    let mut decoder = LineDecoder::new(ctx.cfg.log_format);
+
+    loop {
+        tokio::select! {
+            biased;
+            _ = termination_rx.changed() => return Ok(()),
 
             next_line = lines.next_line() => {
-                if was_file_removed(&file).await? {
-                    return Ok(true)
+                match next_line? {
+                    Some(line) => {
+                        debug!("Reading line from stdin");
+                        if let Some(line) = decoder.decode(&line) {
+                            ctx.pipeline.submit(line.into_owned(), &source).await;
+                        }
+                    }
+                    None => {
+                        // the hasura process closed its output, there is nothing more
+                        // to follow so the remaining tasks are asked to shut down as well
+                        warn!("Reached end of stdin, terminating");
+                        let _ = termination_tx.send(());
+                        return Ok(());
+                    }
                 }
+            }
+        }
+    }
+}
+
+/// Returns the offset to start reading the file from, only regular files can be positioned,
+/// named pipes are always read from where they are
+fn start_offset(log_file: &str, metadata: &Metadata, start_position: StartPosition, checkpoints: Option<&Checkpoints>) -> u64 {
+    if !metadata.is_file() {
+        return 0;
+    }
+
+    match start_position {
+        StartPosition::Beginning => 0,
+        StartPosition::End => metadata.len(),
+        StartPosition::Checkpoint => checkpoints
+            .and_then(|c| c.get(log_file))
+            // a different inode means the file was replaced, a larger offset that it was truncated
+            .filter(|c| c.inode == metadata.ino() && c.offset <= metadata.len())
+            .map_or(0, |c| c.offset),
+    }
+}
+
+async fn process_file(log_file: &str, file: &File, start_position: StartPosition, ctx: &ReaderContext<'_>, mut termination_rx: watch::Receiver<()>) -> Result<bool> {
+    let source: Arc<str> = Arc::from(source_name(Path::new(log_file)));
+    let metric_obj = ctx.metric_obj;
+    let metadata = file.metadata().await?;
+    let inode = metadata.ino();
+    let is_regular_file = metadata.is_file();
+    // checkpoints are only kept for regular files, offsets of named pipes are meaningless
+    let checkpoints = ctx.checkpoints.filter(|_| is_regular_file);
+    let mut offset = start_offset(log_file, &metadata, start_position, checkpoints);
+    let mut progress = checkpoints.map(|c| c.track(log_file, inode, offset));
+    let mut fingerprint = match is_regular_file {
+        true => Some(Fingerprint::new(file.try_clone().await?.into_std().await)),
+        false => None,
+    };
+
+    let mut handle = file.try_clone().await?;
+    if offset > 0 {
+        info!("Continuing hasura log file {} at offset {}", log_file, offset);
+        handle.seek(SeekFrom::Start(offset)).await?;
+    }
+
+    let mut reader = BufReader::new(handle);
+    let mut line = String::new();
+
+    let mut decoder = LineDecoder::new(ctx.cfg.log_format);
+    let mut waiter = FileWaiter::new(log_file, ctx.cfg.sleep_time);
+    let _follow_mode = FollowModeGuard::new(metric_obj.LOG_FOLLOW_MODE.with_label_values(&metric_obj.log_labels(&source, &[waiter.mode()])));
+
+    // rotation is checked once everything available was read and again after every wait before reading
+    // on, a file truncated and written past the offset meanwhile would otherwise be read on at the offset
+    let mut check_now = false;
+    let mut checked = false;
+    loop {
+        if std::mem::take(&mut check_now) {
+            checked = true;
+            match check_rotation(log_file, file, fingerprint.as_mut(), inode, offset).await? {
+                Rotation::None => (),
+                Rotation::Replaced => {
+                    info!("Hasura log file {} was removed or replaced", log_file);
+                    metric_obj.LOG_FILE_ROTATIONS
+                        .with_label_values(&metric_obj.log_labels(&source, &["replaced"]))
+                        .inc();
+                    return Ok(true);
+                }
+                Rotation::Truncated => {
+                    info!("Hasura log file {} was truncated, reading it from the beginning", log_file);
+                    metric_obj.LOG_FILE_ROTATIONS
+                        .with_label_values(&metric_obj.log_labels(&source, &["truncated"]))
+                        .inc();
+
+                    if ctx.cfg.rotated_catch_up && !catch_up_rotated_file(log_file, offset, &source, &mut decoder, ctx.pipeline, &mut termination_rx).await {
+                        return Ok(false);
+                    }
 
-                if let Some(line) = next_line? {
+                    line.clear();
+                    offset = 0;
+                    reader.seek(SeekFrom::Start(0)).await?;
+                    progress = checkpoints.map(|c| c.track(log_file, inode, offset));
+                    if let Some(fingerprint) = &mut fingerprint {
+                        fingerprint.reset();
+                    }
+                    continue;
+                }
+            }
+        }
+
+        tokio::select! {
+            biased;
+            _ = termination_rx.changed() => return Ok(false),
+
+            read = reader.read_line(&mut line) => {
+                let read = read?;
+
+                if read > 0 && line.ends_with('\n') {
                     debug!("Reading line from logfile");
-                    logprocessor::log_processor(&line, metric_obj).await;
-                } else {
-                    time::sleep(Duration::from_millis(sleep_time)).await;
+                    let (start, end) = (offset, offset + line.len() as u64);
+                    match decoder.decode(line.trim_end_matches(['\r', '\n'])) {
+                        Some(decoded) => {
+                            // the checkpoint only moves past the line once a worker processed it
+                            let ack = progress.as_ref().map(|p| p.submitted(start, end));
+                            ctx.pipeline.submit_tracked(decoded.into_owned(), &source, ack).await;
+                        }
+                        None => {
+                            if let Some(progress) = &progress {
+                                progress.skipped(end);
+                            }
+                        }
+                    }
+
+                    offset = end;
+                    checked = false;
+                    line.clear();
+                    continue;
+                }
+
+                // an incomplete line is kept in the buffer until the rest was written
+                if read == 0 && !checked {
+                    check_now = true;
+                    continue;
                 }
+
+                // the wait can take up to the notify fallback interval, termination is checked meanwhile
+                tokio::select! {
+                    biased;
+                    _ = termination_rx.changed() => return Ok(false),
+                    _ = waiter.wait() => (),
+                }
+                check_now = true;
+            }
+        }
+    }
+}
+
+/// Counts a followed file in the follow mode gauge for as long as it is followed
+struct FollowModeGuard(IntGauge);
+
+impl FollowModeGuard {
+    fn new(gauge: IntGauge) -> FollowModeGuard {
+        gauge.inc();
+        FollowModeGuard(gauge)
+    }
+}
+
+impl Drop for FollowModeGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+enum Rotation {
+    None,
+    /// The file was deleted, or renamed and another file was created in its place
+    Replaced,
+    /// The file was truncated in place, like logrotate's copytruncate does
+    Truncated,
+}
+
+/// The first bytes of a followed regular file. A truncation is noticed by the file being shorter
+/// than the read offset, but the writer may have written past the offset again before the file
+/// was checked. The first bytes of the file tell that it was truncated in this case.
+struct Fingerprint {
+    file: std::fs::File,
+    prefix: Vec<u8>,
+}
+
+impl Fingerprint {
+    fn new(file: std::fs::File) -> Fingerprint {
+        Fingerprint { file, prefix: Vec::new() }
+    }
+
+    /// Compares the first bytes of the file with the bytes seen before, up to the read offset.
+    /// Returns false if they changed, otherwise the bytes seen are extended up to the offset.
+    fn check(&mut self, offset: u64) -> Result<bool> {
+        let mut current = vec![0; offset.min(FINGERPRINT_SIZE) as usize];
+        let read = read_at_most(&self.file, &mut current)?;
+        current.truncate(read);
+        if !current.starts_with(&self.prefix) {
+            return Ok(false);
+        }
+        self.prefix = current;
+        Ok(true)
+    }
+
+    fn reset(&mut self) {
+        self.prefix.clear();
+    }
+}
+
+/// Reads the beginning of the file into the buffer, returns the number of bytes read
+fn read_at_most(file: &std::fs::File, buf: &mut [u8]) -> Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match file.read_at(&mut buf[read..], read as u64)? {
+            0 => break,
+            n => read += n,
+        }
+    }
+    Ok(read)
+}
+
+async fn check_rotation(log_file: &str, file: &File, fingerprint: Option<&mut Fingerprint>, inode: u64, offset: u64) -> Result<Rotation> {
+    let metadata = file.metadata().await?;
+    if metadata.nlink() == 0 {
+        return Ok(Rotation::Replaced);
+    }
+
+    // only regular files have a fingerprint, named pipes can't be replaced or truncated
+    if let Some(fingerprint) = fingerprint {
+        match tokio::fs::metadata(log_file).await {
+            Ok(current) if current.ino() != inode => return Ok(Rotation::Replaced),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Rotation::Replaced),
+            _ => (),
+        }
+
+        if metadata.len() < offset || !fingerprint.check(offset)? {
+            return Ok(Rotation::Truncated);
+        }
+    }
+
+    Ok(Rotation::None)
+}
+
+/// After a copytruncate rotation, the lines written between the last read and the truncation
+/// only exist in the rotated copy. Reads them from `<log_file>.1` or `<log_file>.1.gz`,
+/// skipping the part of the copy which was already processed. Returns false if terminated meanwhile.
+async fn catch_up_rotated_file(log_file: &str, offset: u64, source: &Arc<str>, decoder: &mut LineDecoder, pipeline: &Pipeline, termination_rx: &mut watch::Receiver<()>) -> bool {
+    let candidates = [
+        (format!("{}.1", log_file), false),
+        (format!("{}.1.gz", log_file), true),
+    ];
+
+    // the copy is read line by line while the lines are submitted, it is never held in memory as a whole
+    let (lines_tx, mut lines_rx) = mpsc::channel(ROTATED_LINES_BUFFER);
+    let reader = tokio::task::spawn_blocking(move || read_rotated_remainder(&candidates, offset, &lines_tx));
+    loop {
+        let line = tokio::select! {
+            biased;
+            _ = termination_rx.changed() => return false,
+            line = lines_rx.recv() => line,
+        };
+        let line = match line {
+            Some(line) => line,
+            None => break,
+        };
+        if let Some(line) = decoder.decode(&line) {
+            // the submit blocks on a full queue, which is not drained anymore once terminated
+            tokio::select! {
+                biased;
+                _ = termination_rx.changed() => return false,
+                _ = pipeline.submit(line.into_owned(), source) => (),
             }
         }
     }
+
+    match reader.await {
+        Ok(Ok(Some((rotated_file, lines)))) => {
+            info!("Read {} remaining lines from rotated log file {}", lines, rotated_file);
+        }
+        Ok(Ok(None)) => {
+            warn!("No rotated copy of hasura log file {} found, lines written before the rotation may be missing", log_file);
+        }
+        Ok(Err(e)) => {
+            warn!("Failed to read rotated copy of hasura log file {}: {}", log_file, e);
+        }
+        Err(e) => {
+            warn!("Failed to read rotated copy of hasura log file {}: {}", log_file, e);
+        }
+    }
+    true
 }
 
-async fn was_file_removed(file: &File) -> Result<bool> {
-    Ok(file.metadata().await?.nlink() == 0)
+/// Sends the lines of the first rotated copy after the offset, returns the copy and the number of lines sent
+fn read_rotated_remainder(candidates: &[(String, bool)], offset: u64, lines_tx: &mpsc::Sender<String>) -> Result<Option<(String, usize)>> {
+    for (rotated_file, compressed) in candidates {
+        let file = match std::fs::File::open(rotated_file) {
+            Ok(file) => file,
+            Err(_) => continue,
+        };
+
+        let reader: Box<dyn Read> = if *compressed {
+            Box::new(GzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+        let mut reader = std::io::BufReader::new(reader);
+
+        // a copy shorter than what was read already can't be the copy of the followed file
+        let skipped = std::io::copy(&mut (&mut reader).take(offset), &mut std::io::sink())?;
+        if skipped < offset {
+            continue;
+        }
+
+        let mut lines = 0;
+        for line in reader.lines() {
+            // the receiver is gone once the adapter terminates
+            if lines_tx.blocking_send(line?).is_err() {
+                break;
+            }
+            lines += 1;
+        }
+        return Ok(Some((rotated_file.clone(), lines)));
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestFile {
+        path: String,
+        checkpoints: String,
+    }
+
+    impl TestFile {
+        fn new(name: &str, content: &str) -> TestFile {
+            let dir = std::env::temp_dir();
+            let path = dir.join(format!("metrics-logreader-{}-{}.log", std::process::id(), name));
+            let checkpoints = dir.join(format!("metrics-logreader-{}-{}.json", std::process::id(), name));
+            std::fs::write(&path, content).unwrap();
+            TestFile {
+                path: path.to_str().unwrap().to_string(),
+                checkpoints: checkpoints.to_str().unwrap().to_string(),
+            }
+        }
+
+        fn metadata(&self) -> Metadata {
+            std::fs::metadata(&self.path).unwrap()
+        }
+
+        /// Checkpoints holding the given offset and inode for the file
+        async fn checkpoints(&self, inode: u64, offset: u64) -> Checkpoints {
+            let content = serde_json::json!({ "files": { &self.path: { "inode": inode, "offset": offset } } });
+            std::fs::write(&self.checkpoints, content.to_string()).unwrap();
+            Checkpoints::load(&self.checkpoints).await
+        }
+    }
+
+    impl Drop for TestFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+            let _ = std::fs::remove_file(&self.checkpoints);
+        }
+    }
+
+    #[tokio::test]
+    async fn starts_at_configured_position() {
+        let file = TestFile::new("position", "{}\n{}\n");
+        let metadata = file.metadata();
+        assert_eq!(start_offset(&file.path, &metadata, StartPosition::Beginning, None), 0);
+        assert_eq!(start_offset(&file.path, &metadata, StartPosition::End, None), 6);
+        assert_eq!(start_offset(&file.path, &metadata, StartPosition::Checkpoint, None), 0);
+    }
+
+    #[tokio::test]
+    async fn starts_at_checkpoint_of_same_file() {
+        let file = TestFile::new("checkpoint", "{}\n{}\n");
+        let metadata = file.metadata();
+        let checkpoints = file.checkpoints(metadata.ino(), 3).await;
+        assert_eq!(start_offset(&file.path, &metadata, StartPosition::Checkpoint, Some(&checkpoints)), 3);
+        // the checkpoint is only used if asked for
+        assert_eq!(start_offset(&file.path, &metadata, StartPosition::Beginning, Some(&checkpoints)), 0);
+    }
+
+    #[tokio::test]
+    async fn ignores_checkpoint_of_replaced_file() {
+        let file = TestFile::new("replaced", "{}\n{}\n");
+        let metadata = file.metadata();
+        let checkpoints = file.checkpoints(metadata.ino() + 1, 3).await;
+        assert_eq!(start_offset(&file.path, &metadata, StartPosition::Checkpoint, Some(&checkpoints)), 0);
+    }
+
+    #[tokio::test]
+    async fn ignores_checkpoint_past_end_of_truncated_file() {
+        let file = TestFile::new("truncated", "{}\n{}\n");
+        let metadata = file.metadata();
+        let checkpoints = file.checkpoints(metadata.ino(), 7).await;
+        assert_eq!(start_offset(&file.path, &metadata, StartPosition::Checkpoint, Some(&checkpoints)), 0);
+        let checkpoints = file.checkpoints(metadata.ino(), 6).await;
+        assert_eq!(start_offset(&file.path, &metadata, StartPosition::Checkpoint, Some(&checkpoints)), 6);
+    }
+
+    #[tokio::test]
+    async fn detects_truncation_past_the_offset() {
+        let file = TestFile::new("fingerprint", "{\"n\":1}\n{\"n\":2}\n");
+        let handle = File::open(&file.path).await.unwrap();
+        let inode = file.metadata().ino();
+        let mut fingerprint = Fingerprint::new(handle.try_clone().await.unwrap().into_std().await);
+        assert!(matches!(check_rotation(&file.path, &handle, Some(&mut fingerprint), inode, 16).await.unwrap(), Rotation::None));
+
+        // appended lines don't change the fingerprint
+        std::fs::write(&file.path, "{\"n\":1}\n{\"n\":2}\n{\"n\":3}\n").unwrap();
+        assert!(matches!(check_rotation(&file.path, &handle, Some(&mut fingerprint), inode, 24).await.unwrap(), Rotation::None));
+
+        // truncated in place and written past the offset before the file was checked
+        std::fs::write(&file.path, "{\"n\":4}\n{\"n\":5}\n{\"n\":6}\n{\"n\":7}\n").unwrap();
+        assert_eq!(file.metadata().ino(), inode);
+        assert!(matches!(check_rotation(&file.path, &handle, Some(&mut fingerprint), inode, 24).await.unwrap(), Rotation::Truncated));
+
+        std::fs::write(&file.path, "{}\n").unwrap();
+        fingerprint.reset();
+        assert!(matches!(check_rotation(&file.path, &handle, Some(&mut fingerprint), inode, 24).await.unwrap(), Rotation::Truncated));
+        assert!(matches!(check_rotation(&file.path, &handle, Some(&mut fingerprint), inode, 3).await.unwrap(), Rotation::None));
+    }
+
+    #[tokio::test]
+    async fn detects_replaced_files() {
+        let file = TestFile::new("rotation", "{}\n");
+        let handle = File::open(&file.path).await.unwrap();
+        let inode = file.metadata().ino();
+        let mut fingerprint = Fingerprint::new(handle.try_clone().await.unwrap().into_std().await);
+
+        std::fs::remove_file(&file.path).unwrap();
+        std::fs::write(&file.path, "{}\n").unwrap();
+        assert!(matches!(check_rotation(&file.path, &handle, Some(&mut fingerprint), inode, 3).await.unwrap(), Rotation::Replaced));
+    }
+
+    /// Lines the rotated copy candidates send after the offset, with the copy they were read from
+    async fn rotated_remainder(candidates: Vec<(String, bool)>, offset: u64) -> (Option<(String, usize)>, Vec<String>) {
+        // a buffer of a single line makes the reader wait for the receiver
+        let (lines_tx, mut lines_rx) = mpsc::channel(1);
+        let reader = tokio::task::spawn_blocking(move || read_rotated_remainder(&candidates, offset, &lines_tx).unwrap());
+        let mut lines = Vec::new();
+        while let Some(line) = lines_rx.recv().await {
+            lines.push(line);
+        }
+        (reader.await.unwrap(), lines)
+    }
+
+    #[tokio::test]
+    async fn reads_remainder_of_rotated_copies() {
+        let plain = TestFile::new("rotated-plain", "{\"n\":1}\n{\"n\":2}\n{\"n\":3}\n");
+        let compressed = TestFile::new("rotated-gz", "");
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, b"{\"n\":4}\n{\"n\":5}\n").unwrap();
+        std::fs::write(&compressed.path, encoder.finish().unwrap()).unwrap();
+
+        let (rotated, lines) = rotated_remainder(vec![(plain.path.clone(), false), (compressed.path.clone(), true)], 8).await;
+        assert_eq!(rotated, Some((plain.path.clone(), 2)));
+        assert_eq!(lines, vec!["{\"n\":2}", "{\"n\":3}"]);
+
+        // copies shorter than the offset are skipped
+        let (rotated, lines) = rotated_remainder(vec![(plain.path.clone(), false), (compressed.path.clone(), true)], 16).await;
+        assert_eq!(rotated, Some((plain.path.clone(), 1)));
+        assert_eq!(lines, vec!["{\"n\":3}"]);
+        let (rotated, lines) = rotated_remainder(vec![(format!("{}.missing", plain.path), false), (compressed.path.clone(), true)], 8).await;
+        assert_eq!(rotated, Some((compressed.path.clone(), 1)));
+        assert_eq!(lines, vec!["{\"n\":5}"]);
+        let (rotated, lines) = rotated_remainder(vec![(compressed.path.clone(), true)], 32).await;
+        assert_eq!(rotated, None);
+        assert!(lines.is_empty());
+    }
+
+    #[tokio::test]
+    async fn stops_reading_rotated_copy_without_receiver() {
+        let file = TestFile::new("rotated-closed", "{}\n{}\n{}\n");
+        let (lines_tx, lines_rx) = mpsc::channel(1);
+        drop(lines_rx);
+        let candidates = vec![(file.path.clone(), false)];
+        let rotated = tokio::task::spawn_blocking(move || read_rotated_remainder(&candidates, 0, &lines_tx).unwrap()).await.unwrap();
+        assert_eq!(rotated, Some((file.path.clone(), 0)));
+    }
 }
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use actix_web::{App, get, HttpServer, Responder};
+use actix_web::{App, get, HttpServer, Responder, web};
 
 use clap::Parser;
 use clap::builder::TypedValueParser;
@@ -10,11 +10,31 @@ use log::{info, warn, debug, error};
 
 use prometheus::{Encoder, TextEncoder};
 use tokio::sync::watch;
-use crate::telemetry::Telemetry;
+use crate::cardinality::CardinalityLimits;
+use crate::checkpoint::Checkpoints;
+use crate::logprocessor::ProcessorConfig;
+use crate::logreader::ReaderContext;
+use crate::pipeline::Pipeline;
+use crate::rules::Rules;
+use crate::urlnormalize::{UrlNormalizer, UrlRewrite};
+use crate::telemetry::{RequestLabels, Telemetry};
 
 mod logreader;
 mod logprocessor;
 mod collectors;
+mod syslog;
+mod ingest;
+mod checkpoint;
+mod follow;
+mod inputformat;
+mod replay;
+mod unixsocket;
+mod graphql;
+mod pipeline;
+mod cardinality;
+mod rules;
+mod urlnormalize;
+mod staleness;
 
 mod telemetry;
 
@@ -32,12 +52,39 @@ async fn metrics() -> impl Responder {
     String::from_utf8(buffer.clone()).unwrap()
 }
 
-async fn webserver(cfg: &Configuration) -> std::io::Result<()> {
+async fn webserver(cfg: &Configuration, metric_obj: &Telemetry, pipeline: &Pipeline, mut termination_rx: watch::Receiver<()>) -> std::io::Result<()> {
     warn!("Starting metric server @ {}", cfg.listen_addr);
-    HttpServer::new(|| App::new().service(metrics))
+    let metric_obj = metric_obj.clone();
+    let ingest_enabled = cfg.ingest_enabled;
+    if ingest_enabled && cfg.ingest_token.is_none() {
+        warn!("The /ingest endpoint accepts log lines from anyone who can reach {}, consider setting an ingest token", cfg.listen_addr);
+    }
+    let ingest_cfg = web::Data::new(ingest::IngestConfig {
+        max_body_size: cfg.ingest_max_body_size,
+        token: cfg.ingest_token.clone(),
+        log_format: cfg.log_format,
+        pipeline: pipeline.clone(),
+        termination_rx: termination_rx.clone(),
+    });
+    let server = HttpServer::new(move || {
+            App::new()
+                .app_data(web::Data::new(metric_obj.clone()))
+                .app_data(ingest_cfg.clone())
+                .service(metrics)
+                .configure(|app| if ingest_enabled { app.service(ingest::ingest); })
+        })
         .bind(&cfg.listen_addr)?
-        .run()
-        .await
+        .run();
+
+    // stop the server when another task (e.g. the stdin reader on EOF) requests termination
+    let handle = server.handle();
+    tokio::spawn(async move {
+        if termination_rx.changed().await.is_ok() {
+            handle.stop(true).await;
+        }
+    });
+
+    server.await
 }
 
 #[derive(clap::ValueEnum, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -46,6 +93,7 @@ pub(crate) enum Collectors {
     EventTriggers,
     ScheduledEvents,
     MetadataInconsistency,
+    RestEndpoints,
 }
 
 fn key_value_parser(input: &str) -> Result<(String, String), String> {
@@ -56,6 +104,25 @@ fn key_value_parser(input: &str) -> Result<(String, String), String> {
     }
 }
 
+fn cardinality_limit_parser(input: &str) -> Result<(String, usize), String> {
+    let (metric, limit) = key_value_parser(input)?;
+    match limit.parse() {
+        Ok(limit) => Ok((metric, limit)),
+        Err(_) => Err(format!("invalid metric=limit: `{}` is not a number", limit)),
+    }
+}
+
+fn source_label_parser(input: &str) -> Result<String, String> {
+    let valid = Regex::new(r"^[a-zA-Z_][a-zA-Z0-9_]*$").unwrap().is_match(input) && !input.starts_with("__");
+    if !valid {
+        return Err(format!("invalid label name `{}`", input));
+    }
+    if telemetry::LOG_LABEL_NAMES.contains(&input) {
+        return Err(format!("`{}` is already a label of the metrics derived from the log", input));
+    }
+    Ok(input.to_string())
+}
+
 /// Implementation for [`ValueParser::string`]
 ///
 /// Useful for composing new [`TypedValueParser`]s
@@ -87,9 +154,18 @@ impl TypedValueParser for MapValueParser {
     }
 }
 
+#[derive(clap::Subcommand, Debug)]
+pub(crate) enum Command {
+    /// Replays a captured hasura log and prints the resulting metrics, without starting the server
+    Replay(replay::ReplayArgs),
+}
+
 #[derive(Parser,Debug)]
-#[clap(author, version, about)]
+#[clap(author, version, about, subcommand_negates_reqs = true)]
 pub(crate) struct Configuration {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
     #[clap(name ="listen", long = "listen", env = "LISTEN_ADDR", default_value = "0.0.0.0:9090")]
     listen_addr: String,
 
@@ -99,8 +175,109 @@ pub(crate) struct Configuration {
     #[clap(name ="hasura-admin-secret", long = "hasura-admin-secret", env = "HASURA_GRAPHQL_ADMIN_SECRET")]
     hasura_admin: Option<String>,
 
-    #[clap(name ="logfile", long = "logfile", env = "LOG_FILE")]
-    log_file: String,
+    /// Hasura log files to follow, glob patterns or - for stdin
+    #[clap(name ="logfile", long = "logfile", env = "LOG_FILE", value_parser, value_delimiter(';'), required_unless_present_any = ["syslog-tcp-listen", "syslog-udp-listen", "unix-socket", "unix-datagram-socket", "ingest"])]
+    log_files: Vec<String>,
+
+    /// Format of the log lines, docker and cri unwrap the envelope of the container runtime
+    #[clap(name ="log-format", long = "log-format", env = "LOG_FORMAT", value_enum, default_value = "hasura")]
+    log_format: inputformat::InputFormat,
+
+    /// Where to start reading log files which exist at startup, checkpoint continues at the stored offset
+    #[clap(name ="start-position", long = "start-position", env = "START_POSITION", value_enum, default_value = "beginning", default_value_if("checkpoint-file", None, Some("checkpoint")))]
+    start_position: logreader::StartPosition,
+
+    /// File storing the read offsets of the followed log files
+    #[clap(name ="checkpoint-file", long = "checkpoint-file", env = "CHECKPOINT_FILE")]
+    checkpoint_file: Option<String>,
+
+    /// Interval in milliseconds in which the checkpoint file is written
+    #[clap(name ="checkpoint-interval", long = "checkpoint-interval", env = "CHECKPOINT_INTERVAL", default_value = "5000")]
+    checkpoint_interval: u64,
+
+    /// After a copytruncate rotation, read the lines missed before the truncation from <logfile>.1 or <logfile>.1.gz
+    #[clap(name ="rotated-catch-up", long = "rotated-catch-up", env = "ROTATED_CATCH_UP")]
+    rotated_catch_up: bool,
+
+    /// Label name of the log source (the file name) on the metrics derived from the log
+    #[clap(name ="source-label", long = "source-label", env = "SOURCE_LABEL", value_parser = source_label_parser)]
+    source_label: Option<String>,
+
+    /// Add the hasura role as label to the http request metrics
+    #[clap(name ="role-label", long = "role-label", env = "ROLE_LABEL")]
+    role_label: bool,
+
+    /// Roles kept in the role label, other roles are reported as __other__
+    #[clap(name ="role-allowlist", long = "role-allowlist", env = "ROLE_ALLOWLIST", value_parser, value_delimiter(';'))]
+    role_allowlist: Vec<String>,
+
+    /// Request header or session variable naming the client, adds it as client label to the http request metrics
+    #[clap(name ="client-header", long = "client-header", env = "CLIENT_HEADER")]
+    client_header: Option<String>,
+
+    /// Clients kept in the client label, other clients are reported as __other__
+    #[clap(name ="client-allowlist", long = "client-allowlist", env = "CLIENT_ALLOWLIST", value_parser, value_delimiter(';'))]
+    client_allowlist: Vec<String>,
+
+    /// Rewrites of the url label, as <regex>=<replacement>
+    #[clap(name ="url-rewrite", long = "url-rewrite", env = "URL_REWRITE", value_parser = urlnormalize::url_rewrite_parser, value_delimiter(';'))]
+    url_rewrites: Vec<UrlRewrite>,
+
+    /// JSON file with rules deriving custom metrics from log lines
+    #[clap(name ="rules-file", long = "rules-file", env = "RULES_FILE")]
+    rules_file: Option<String>,
+
+    /// Maximum number of values per label of a metric derived from the log, further values are folded into __other__, 0 disables the limit
+    #[clap(name ="cardinality-limit", long = "cardinality-limit", env = "CARDINALITY_LIMIT", default_value = "1000")]
+    cardinality_limit: usize,
+
+    /// Cardinality limits of single metrics, as <metric name>=<limit>
+    #[clap(name ="cardinality-limits", long = "cardinality-limits", env = "CARDINALITY_LIMITS", value_parser = cardinality_limit_parser, value_delimiter(';'))]
+    cardinality_limits: Vec<(String, usize)>,
+
+    /// Address to receive hasura logs from a syslog relay over TCP, e.g. 0.0.0.0:6514
+    #[clap(name ="syslog-tcp-listen", long = "syslog-tcp-listen", env = "SYSLOG_TCP_LISTEN_ADDR")]
+    syslog_tcp_addr: Option<String>,
+
+    /// Address to receive hasura logs from a syslog relay over UDP, e.g. 0.0.0.0:514
+    #[clap(name ="syslog-udp-listen", long = "syslog-udp-listen", env = "SYSLOG_UDP_LISTEN_ADDR")]
+    syslog_udp_addr: Option<String>,
+
+    /// Path of a unix stream socket to receive newline delimited hasura log lines on
+    #[clap(name ="unix-socket", long = "unix-socket", env = "UNIX_SOCKET")]
+    unix_socket: Option<String>,
+
+    /// Path of a unix datagram socket to receive one hasura log line per datagram on
+    #[clap(name ="unix-datagram-socket", long = "unix-datagram-socket", env = "UNIX_DATAGRAM_SOCKET")]
+    unix_datagram_socket: Option<String>,
+
+    /// Accept hasura log lines pushed to POST /ingest on the listen address
+    #[clap(name ="ingest", long = "ingest", env = "INGEST_ENABLED")]
+    ingest_enabled: bool,
+
+    /// Maximum size in bytes of the body of an ingest request
+    #[clap(name ="ingest-max-body-size", long = "ingest-max-body-size", env = "INGEST_MAX_BODY_SIZE", default_value = "10485760")]
+    ingest_max_body_size: usize,
+
+    /// Bearer token ingest requests must carry in the Authorization header
+    #[clap(name ="ingest-token", long = "ingest-token", env = "INGEST_TOKEN", hide_env_values = true)]
+    ingest_token: Option<String>,
+
+    /// Number of log lines the queue between the readers and the workers holds
+    #[clap(name ="pipeline-capacity", long = "pipeline-capacity", env = "PIPELINE_CAPACITY", default_value = "10000")]
+    pipeline_capacity: usize,
+
+    /// Number of workers processing the queued log lines
+    #[clap(name ="pipeline-workers", long = "pipeline-workers", env = "PIPELINE_WORKERS", default_value = "1")]
+    pipeline_workers: usize,
+
+    /// What to do with new log lines when the queue is full
+    #[clap(name ="pipeline-overflow", long = "pipeline-overflow", env = "PIPELINE_OVERFLOW", value_enum, default_value = "block")]
+    pipeline_overflow: pipeline::OverflowPolicy,
+
+    /// Milliseconds without a new log line after which the log stream is reported as stale, 0 disables the check
+    #[clap(name ="stale-threshold", long = "stale-threshold", env = "STALE_THRESHOLD", default_value = "300000")]
+    stale_threshold: u64,
 
     #[clap(name ="sleep", long = "sleep", env = "SLEEP_TIME", default_value = "1000")]
     sleep_time: u64,
@@ -111,12 +288,24 @@ pub(crate) struct Configuration {
     #[clap(name ="exclude-collectors", long = "exclude-collectors", env = "EXCLUDE_COLLECTORS", value_parser, value_delimiter(';'))]
     disabled_collectors: Vec<Collectors>,
 
+    /// Metrics derived from the log which are not exported
+    #[clap(name ="exclude-log-metrics", long = "exclude-log-metrics", env = "EXCLUDE_LOG_METRICS", value_parser, value_delimiter(';'))]
+    disabled_log_metrics: Vec<logprocessor::LogMetrics>,
+
     #[clap(name ="common-labels", short = 'l', long = "common-labels", env = "COMMON_LABELS", value_parser = MapValueParser::new())]
     common_labels: Option<HashMap<String,String>>,
 
     #[clap(name ="histogram-buckets", long = "histogram-buckets", env = "HISTOGRAM_BUCKETS", value_parser, value_delimiter(';'))]
     histogram_buckets: Vec<f64>,
 
+    /// Buckets in seconds of the request duration histogram, the prometheus default buckets if not set
+    #[clap(name ="request-duration-buckets", long = "request-duration-buckets", env = "REQUEST_DURATION_BUCKETS", value_parser, value_delimiter(';'))]
+    request_duration_buckets: Vec<f64>,
+
+    /// Buckets in bytes of the response size histogram, 1KiB to 64MiB if not set
+    #[clap(name ="response-size-buckets", long = "response-size-buckets", env = "RESPONSE_SIZE_BUCKETS", value_parser, value_delimiter(';'))]
+    response_size_buckets: Vec<f64>,
+
     #[clap(name ="concurrency-limit", long = "concurrency-limit", env = "CONCURRENCY_LIMIT", default_value = "0")]
     concurrency_limit: usize,
 }
@@ -128,10 +317,39 @@ async fn signal_handler_ctrl_c(tx: watch::Sender<()>) -> std::io::Result<()> {
     Ok(())
 }
 
-fn signal_handler() -> watch::Receiver<()> {
+fn signal_handler() -> (watch::Sender<()>, watch::Receiver<()>) {
     let (terminate_tx, terminate_rx) = watch::channel(());
-    tokio::spawn(signal_handler_ctrl_c(terminate_tx));
-    terminate_rx
+    tokio::spawn(signal_handler_ctrl_c(terminate_tx.clone()));
+    (terminate_tx, terminate_rx)
+}
+
+fn create_telemetry(cfg: &Configuration) -> Result<Telemetry, String> {
+    if let (Some(source_label), Some(common_labels)) = (&cfg.source_label, &cfg.common_labels) {
+        if common_labels.contains_key(source_label) {
+            return Err(format!("source label '{}' is already a common label", source_label));
+        }
+    }
+
+    let mut telemetry = Telemetry::new(
+        cfg.common_labels.clone().unwrap_or_default(),
+        cfg.histogram_buckets.clone(),
+        cfg.request_duration_buckets.clone(),
+        cfg.response_size_buckets.clone(),
+        RequestLabels {
+            role: cfg.role_label,
+            client: cfg.client_header.is_some(),
+        },
+        CardinalityLimits {
+            default_limit: cfg.cardinality_limit,
+            metric_limits: cfg.cardinality_limits.iter().cloned().collect(),
+        },
+        cfg.source_label.clone(),
+    );
+    telemetry.url_normalizer = UrlNormalizer::new(cfg.url_rewrites.clone());
+    if let Some(path) = &cfg.rules_file {
+        telemetry.rules = Rules::load(path, &cfg.common_labels.clone().unwrap_or_default(), &cfg.histogram_buckets, &cfg.source_label)?;
+    }
+    Ok(telemetry)
 }
 
 #[tokio::main]
@@ -139,12 +357,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
     let mut config = Configuration::parse();
 
+    if let Some(Command::Replay(args)) = &config.command {
+        let metric_obj = create_telemetry(&config)?;
+        replay::replay(&config, args, &metric_obj).await?;
+        return Ok(());
+    }
+
     if config.hasura_admin.is_none() {
         let admin_collectors = [
             Collectors::CronTriggers,
             Collectors::EventTriggers,
             Collectors::ScheduledEvents,
             Collectors::MetadataInconsistency,
+            Collectors::RestEndpoints,
         ];
 
         config.disabled_collectors.extend_from_slice(&admin_collectors);
@@ -152,20 +377,58 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         warn!("No Hasura admin secret provided, disabling following collectors: {:?}", &admin_collectors);
     }
 
+    if config.start_position == logreader::StartPosition::Checkpoint && config.checkpoint_file.is_none() {
+        return Err("start position 'checkpoint' requires a checkpoint file".into());
+    }
+
     config.disabled_collectors.sort();
     config.disabled_collectors.dedup();
 
-    info!("hasura-metrics-adapter on {0} for hasura at {1} parsing hasura log '{2}'", config.listen_addr, config.hasura_addr, config.log_file);
+    if config.disabled_collectors.contains(&Collectors::RestEndpoints) {
+        warn!("RESTified endpoints are not fetched, requests to them are reported with url '{}' unless a url rewrite matches", telemetry::OTHER_LABEL_VALUE);
+    }
+
+    info!("hasura-metrics-adapter on {0} for hasura at {1} parsing hasura log '{2}'", config.listen_addr, config.hasura_addr, config.log_files.join(";"));
 
     debug!("Configuration: {:?}", config);
 
-    let terminate_rx = signal_handler();
+    let (terminate_tx, terminate_rx) = signal_handler();
+
+    let metric_obj: Telemetry = create_telemetry(&config)?;
+
+    let checkpoints = match &config.checkpoint_file {
+        Some(path) => Some(Checkpoints::load(path).await),
+        None => None,
+    };
 
-    let metric_obj: Telemetry = Telemetry::new(config.common_labels.clone().unwrap_or_default(),config.histogram_buckets.clone());
+    let pipeline = Pipeline::new(config.pipeline_capacity, config.pipeline_overflow, ProcessorConfig::new(&config), &metric_obj);
+    let reader_ctx = ReaderContext {
+        cfg: &config,
+        metric_obj: &metric_obj,
+        checkpoints: checkpoints.as_ref(),
+        pipeline: &pipeline,
+    };
 
     let res = tokio::try_join!(
-        webserver(&config),
-        logreader::read_file(&config.log_file, &metric_obj, config.sleep_time, terminate_rx.clone()),
+        webserver(&config, &metric_obj, &pipeline, terminate_rx.clone()),
+        logreader::read_files(&reader_ctx, terminate_tx, terminate_rx.clone()),
+        async {
+            pipeline::run_workers(&pipeline, config.pipeline_workers, terminate_rx.clone()).await?;
+            // the last flush waits for the workers, so it covers the lines left in the queue on termination
+            match &checkpoints {
+                Some(checkpoints) => checkpoints.flush().await,
+                None => Ok(()),
+            }
+        },
+        async {
+            match &checkpoints {
+                Some(checkpoints) => checkpoint::run_checkpoint_writer(checkpoints, config.checkpoint_interval, terminate_rx.clone()).await,
+                None => Ok(()),
+            }
+        },
+        syslog::run_syslog_receiver(&config, &metric_obj, &pipeline, terminate_rx.clone()),
+        unixsocket::run_unix_socket_receiver(&config, &metric_obj, &pipeline, terminate_rx.clone()),
+        staleness::run_staleness_check(&config, &metric_obj, terminate_rx.clone()),
         collectors::run_metadata_collector(&config, &metric_obj, terminate_rx.clone())
     );
 
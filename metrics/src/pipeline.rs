@@ -0,0 +1,175 @@
+use log::{debug, info};
+use std::collections::VecDeque;
+use std::io::Result;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::{watch, Notify};
+
+use crate::checkpoint::LineAck;
+use crate::logprocessor::{self, ProcessorConfig};
+use crate::Telemetry;
+
+/// What to do with a new log line if the queue is full
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait until a worker made room, this slows down the reader
+    Block,
+    /// Drop the oldest queued line to make room for the new one
+    DropOldest,
+}
+
+struct QueuedLine {
+    line: String,
+    source: Arc<str>,
+    enqueued: Instant,
+    /// Completed once the line was processed, for lines of files with checkpoints
+    ack: Option<LineAck>,
+}
+
+struct Queue {
+    lines: Mutex<VecDeque<QueuedLine>>,
+    capacity: usize,
+    overflow: OverflowPolicy,
+    /// Signaled when a line was queued
+    available: Notify,
+    /// Signaled when a line was taken from the queue
+    space: Notify,
+}
+
+/// Bounded queue between the log readers and the workers parsing the log lines
+#[derive(Clone)]
+pub struct Pipeline {
+    queue: Arc<Queue>,
+    processor_cfg: ProcessorConfig,
+    metric_obj: Telemetry,
+}
+
+impl Pipeline {
+    pub fn new(capacity: usize, overflow: OverflowPolicy, processor_cfg: ProcessorConfig, metric_obj: &Telemetry) -> Pipeline {
+        Pipeline {
+            queue: Arc::new(Queue {
+                lines: Mutex::new(VecDeque::with_capacity(capacity)),
+                capacity: capacity.max(1),
+                overflow,
+                available: Notify::new(),
+                space: Notify::new(),
+            }),
+            processor_cfg,
+            metric_obj: metric_obj.clone(),
+        }
+    }
+
+    /// Queues a log line for processing, depending on the overflow policy this waits for
+    /// room in the queue or drops the oldest queued line
+    pub async fn submit(&self, line: String, source: &Arc<str>) {
+        self.submit_tracked(line, source, None).await
+    }
+
+    /// Queues a log line like `submit`, the ack is completed once the line was processed
+    pub async fn submit_tracked(&self, line: String, source: &Arc<str>, ack: Option<LineAck>) {
+        let mut queued = QueuedLine {
+            line,
+            source: source.clone(),
+            enqueued: Instant::now(),
+            ack,
+        };
+
+        loop {
+            let space = self.queue.space.notified();
+            match self.try_push(queued) {
+                Ok(()) => {
+                    self.queue.available.notify_one();
+                    return;
+                }
+                Err(rejected) => queued = rejected,
+            }
+            space.await;
+        }
+    }
+
+    fn try_push(&self, queued: QueuedLine) -> std::result::Result<(), QueuedLine> {
+        let mut lines = self.queue.lines.lock().unwrap();
+        if lines.len() >= self.queue.capacity {
+            match self.queue.overflow {
+                OverflowPolicy::Block => return Err(queued),
+                OverflowPolicy::DropOldest => {
+                    // a dropped line is given up on, the checkpoint moves past it as well
+                    if let Some(ack) = lines.pop_front().and_then(|dropped| dropped.ack) {
+                        ack.complete();
+                    }
+                    self.metric_obj.PIPELINE_DROPPED_LINES.inc();
+                }
+            }
+        }
+
+        lines.push_back(queued);
+        self.metric_obj.PIPELINE_QUEUE_DEPTH.set(lines.len() as i64);
+        Ok(())
+    }
+
+    fn pop(&self) -> Option<QueuedLine> {
+        let mut lines = self.queue.lines.lock().unwrap();
+        let queued = lines.pop_front();
+        self.metric_obj.PIPELINE_QUEUE_DEPTH.set(lines.len() as i64);
+        queued
+    }
+
+    async fn next(&self) -> QueuedLine {
+        loop {
+            let available = self.queue.available.notified();
+            if let Some(queued) = self.pop() {
+                self.queue.space.notify_one();
+                return queued;
+            }
+            available.await;
+        }
+    }
+
+    async fn process(&self, queued: QueuedLine) {
+        let started = Instant::now();
+        self.metric_obj.PIPELINE_STAGE_SECONDS
+            .with_label_values(&["queue"])
+            .observe(started.duration_since(queued.enqueued).as_secs_f64());
+
+        logprocessor::log_processor(&queued.line, &queued.source, &self.processor_cfg, &self.metric_obj).await;
+        if let Some(ack) = queued.ack {
+            ack.complete();
+        }
+
+        self.metric_obj.PIPELINE_STAGE_SECONDS
+            .with_label_values(&["process"])
+            .observe(started.elapsed().as_secs_f64());
+    }
+}
+
+/// Runs the workers processing the queued log lines. On termination the workers process
+/// what is left in the queue before they stop.
+pub async fn run_workers(pipeline: &Pipeline, workers: usize, termination_rx: watch::Receiver<()>) -> Result<()> {
+    info!("Starting {} log processing workers", workers.max(1));
+
+    let handles: Vec<_> = (0..workers.max(1))
+        .map(|_| tokio::spawn(run_worker(pipeline.clone(), termination_rx.clone())))
+        .collect();
+
+    for handle in handles {
+        handle.await?;
+    }
+    Ok(())
+}
+
+async fn run_worker(pipeline: Pipeline, mut termination_rx: watch::Receiver<()>) {
+    loop {
+        tokio::select! {
+            biased;
+            _ = termination_rx.changed() => break,
+
+            queued = pipeline.next() => pipeline.process(queued).await,
+        }
+    }
+
+    while let Some(queued) = pipeline.pop() {
+        pipeline.queue.space.notify_one();
+        pipeline.process(queued).await;
+    }
+    debug!("Log processing worker stopped");
+}
@@ -0,0 +1,185 @@
+use chrono::{DateTime, FixedOffset};
+use log::{info, warn};
+use prometheus::proto::{MetricFamily, MetricType};
+use prometheus::{Encoder, TextEncoder};
+use serde::Deserialize;
+use serde_json::{from_str, json, Map, Value};
+use std::io::Result;
+use tokio::{
+    fs::File,
+    io::{self, AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader},
+};
+
+use crate::inputformat::LineDecoder;
+use crate::logprocessor::ProcessorConfig;
+use crate::logreader::STDIN_LOG_FILE;
+use crate::{logprocessor, Configuration, Telemetry};
+
+/// Source label value of replayed lines
+const REPLAY_SOURCE: &str = "replay";
+
+/// Metrics which carry no meaning for a replayed log
+const REPLAY_EXCLUDED_METRICS: [&str; 2] = ["hasura_log_processing_delay_seconds", "hasura_log_last_processed_timestamp_seconds"];
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// Reads a captured hasura log to its end and prints the resulting metrics
+#[derive(clap::Args, Debug)]
+pub(crate) struct ReplayArgs {
+    /// Log file to replay, '-' reads from stdin
+    #[clap(name ="input", long = "input", default_value = "-")]
+    input: String,
+
+    /// File to write the metrics to, defaults to stdout
+    #[clap(name ="output", long = "output")]
+    output: Option<String>,
+
+    /// Only replay log lines with a timestamp at or after this RFC 3339 timestamp
+    #[clap(name ="from", long = "from", value_parser = parse_time)]
+    from: Option<DateTime<FixedOffset>>,
+
+    /// Only replay log lines with a timestamp before this RFC 3339 timestamp
+    #[clap(name ="to", long = "to", value_parser = parse_time)]
+    to: Option<DateTime<FixedOffset>>,
+
+    #[clap(name ="format", long = "format", value_enum, default_value = "text")]
+    format: OutputFormat,
+}
+
+fn parse_time(input: &str) -> std::result::Result<DateTime<FixedOffset>, String> {
+    DateTime::parse_from_rfc3339(input).map_err(|e| format!("invalid RFC 3339 timestamp `{}`: {}", input, e))
+}
+
+#[derive(Deserialize)]
+struct LogTimestamp {
+    #[serde(rename = "timestamp")]
+    timestamp: String,
+}
+
+impl ReplayArgs {
+    fn in_range(&self, line: &str) -> bool {
+        if self.from.is_none() && self.to.is_none() {
+            return true;
+        }
+
+        let timestamp = from_str::<LogTimestamp>(line)
+            .ok()
+            .and_then(|log| logprocessor::parse_timestamp(&log.timestamp));
+        match timestamp {
+            Some(timestamp) => {
+                self.from.map_or(true, |from| timestamp >= from) && self.to.map_or(true, |to| timestamp < to)
+            }
+            None => false,
+        }
+    }
+}
+
+pub(crate) async fn replay(cfg: &Configuration, args: &ReplayArgs, metric_obj: &Telemetry) -> Result<()> {
+    let input: Box<dyn AsyncRead + Unpin> = if args.input == STDIN_LOG_FILE {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(&args.input).await?)
+    };
+
+    let mut lines = BufReader::new(input).lines();
+    let mut decoder = LineDecoder::new(cfg.log_format);
+    let processor_cfg = ProcessorConfig::new(cfg);
+    let (mut replayed, mut skipped) = (0, 0);
+
+    while let Some(line) = lines.next_line().await? {
+        if let Some(line) = decoder.decode(&line) {
+            if args.in_range(&line) {
+                logprocessor::log_processor(&line, REPLAY_SOURCE, &processor_cfg, metric_obj).await;
+                replayed += 1;
+            } else {
+                skipped += 1;
+            }
+        }
+    }
+    info!("Replayed {} log lines, skipped {} lines outside of the time range", replayed, skipped);
+
+    // metrics which are not derived from the log (e.g. the health check) are never set during
+    // a replay, so empty metric families are left out of the snapshot. The processing delay
+    // only tells how old the archived log is and the processing time is the time of the replay.
+    let metric_families: Vec<MetricFamily> = prometheus::gather()
+        .into_iter()
+        .filter(|family| !is_empty(family) && !REPLAY_EXCLUDED_METRICS.contains(&family.get_name()))
+        .collect();
+
+    let mut buffer = Vec::new();
+    match args.format {
+        OutputFormat::Text => {
+            TextEncoder::new()
+                .encode(&metric_families, &mut buffer)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut buffer, &encode_json(&metric_families))?;
+            buffer.push(b'\n');
+        }
+    }
+
+    match &args.output {
+        Some(output) => tokio::fs::write(output, buffer).await?,
+        None => {
+            let mut stdout = io::stdout();
+            stdout.write_all(&buffer).await?;
+            stdout.flush().await?;
+        }
+    }
+
+    Ok(())
+}
+
+fn is_empty(family: &MetricFamily) -> bool {
+    family.get_metric().iter().all(|metric| match family.get_field_type() {
+        MetricType::COUNTER => metric.get_counter().get_value() == 0.0,
+        MetricType::GAUGE => metric.get_gauge().get_value() == 0.0,
+        MetricType::HISTOGRAM => metric.get_histogram().get_sample_count() == 0,
+        _ => false,
+    })
+}
+
+fn encode_json(metric_families: &[MetricFamily]) -> Value {
+    let families: Vec<Value> = metric_families.iter().map(|family| {
+        let metrics: Vec<Value> = family.get_metric().iter().map(|metric| {
+            let labels: Map<String, Value> = metric.get_label().iter()
+                .map(|label| (label.get_name().to_string(), Value::from(label.get_value())))
+                .collect();
+
+            match family.get_field_type() {
+                MetricType::COUNTER => json!({"labels": labels, "value": metric.get_counter().get_value()}),
+                MetricType::GAUGE => json!({"labels": labels, "value": metric.get_gauge().get_value()}),
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    let buckets: Vec<Value> = histogram.get_bucket().iter()
+                        .map(|bucket| json!({"le": bucket.get_upper_bound(), "count": bucket.get_cumulative_count()}))
+                        .collect();
+                    json!({
+                        "labels": labels,
+                        "count": histogram.get_sample_count(),
+                        "sum": histogram.get_sample_sum(),
+                        "buckets": buckets,
+                    })
+                }
+                other => {
+                    warn!("Metric type {:?} of {} not supported in JSON output", other, family.get_name());
+                    json!({"labels": labels})
+                }
+            }
+        }).collect();
+
+        json!({
+            "name": family.get_name(),
+            "help": family.get_help(),
+            "type": format!("{:?}", family.get_field_type()).to_lowercase(),
+            "metrics": metrics,
+        })
+    }).collect();
+
+    Value::from(families)
+}
@@ -0,0 +1,259 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use log::info;
+use prometheus::{CounterVec, GaugeVec, HistogramOpts, HistogramVec, Opts};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::telemetry::log_label_names;
+
+/// How a rule turns a matching log line into a metric observation
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RuleKind {
+    /// Increments a counter by the value, or by 1 without a value
+    Counter,
+    /// Increments a gauge by the value, or by 1 without a value
+    GaugeInc,
+    /// Decrements a gauge by the value, or by 1 without a value
+    GaugeDec,
+    /// Observes the value in a histogram
+    Histogram,
+}
+
+/// A rule of the rules file
+#[derive(Deserialize, Debug)]
+struct RuleConfig {
+    /// Name of the metric
+    name: String,
+    #[serde(default)]
+    help: String,
+    /// Log type (`type` of the log line) the rule matches
+    log_type: String,
+    /// Log level the rule matches, any level if not set
+    #[serde(default)]
+    level: Option<String>,
+    kind: RuleKind,
+    /// Label names with the JSON pointer into `detail` of the label value
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+    /// JSON pointer into `detail` of the value
+    #[serde(default)]
+    value: Option<String>,
+    /// Histogram buckets, the `--histogram-buckets` are used if not set
+    #[serde(default)]
+    buckets: Vec<f64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RulesFile {
+    rules: Vec<RuleConfig>,
+}
+
+#[derive(Debug)]
+pub enum RuleMetric {
+    Counter(CounterVec),
+    Gauge(GaugeVec),
+    Histogram(HistogramVec),
+}
+
+/// A rule deriving a metric from the log lines of a log type
+#[derive(Debug)]
+pub struct Rule {
+    log_type: String,
+    level: Option<String>,
+    pub kind: RuleKind,
+    label_paths: Vec<String>,
+    value_path: Option<String>,
+    pub metric: RuleMetric,
+}
+
+impl Rule {
+    fn new(rule: RuleConfig, common_labels: &HashMap<String, String>, histogram_buckets: &[f64], source_label: &Option<String>) -> Result<Rule, String> {
+        if rule.kind == RuleKind::Histogram && rule.value.is_none() {
+            return Err(format!("rule '{}': a histogram needs a value", rule.name));
+        }
+
+        let opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name: rule.name.clone(),
+            help: if rule.help.is_empty() { format!("Derived from the {} log by a rule", rule.log_type) } else { rule.help.clone() },
+            const_labels: common_labels.clone(),
+            variable_labels: vec![]
+        };
+        let label_names: Vec<&str> = rule.labels.keys().map(String::as_str).collect();
+        let label_names = log_label_names(&label_names, source_label);
+
+        // the register macros panic on invalid names, an invalid rules file is reported as error instead
+        let metric = match rule.kind {
+            RuleKind::Counter => CounterVec::new(opts, &label_names).map(RuleMetric::Counter),
+            RuleKind::GaugeInc | RuleKind::GaugeDec => GaugeVec::new(opts, &label_names).map(RuleMetric::Gauge),
+            RuleKind::Histogram => {
+                let buckets = if rule.buckets.is_empty() { histogram_buckets.to_vec() } else { rule.buckets.clone() };
+                HistogramVec::new(HistogramOpts { common_opts: opts, buckets }, &label_names).map(RuleMetric::Histogram)
+            }
+        }.and_then(|metric| {
+            match &metric {
+                RuleMetric::Counter(counter) => prometheus::register(Box::new(counter.clone())),
+                RuleMetric::Gauge(gauge) => prometheus::register(Box::new(gauge.clone())),
+                RuleMetric::Histogram(histogram) => prometheus::register(Box::new(histogram.clone())),
+            }?;
+            Ok(metric)
+        }).map_err(|e| format!("rule '{}': {}", rule.name, e))?;
+
+        Ok(Rule {
+            log_type: rule.log_type,
+            level: rule.level,
+            kind: rule.kind,
+            label_paths: rule.labels.into_values().collect(),
+            value_path: rule.value,
+            metric,
+        })
+    }
+
+    fn matches(&self, logtype: &str, level: &str) -> bool {
+        self.log_type == logtype && self.level.as_ref().map_or(true, |rule_level| rule_level == level)
+    }
+
+    /// Label values taken from the log detail, in the order of the label names. Missing values are empty.
+    pub fn label_values(&self, detail: &Value) -> Vec<String> {
+        self.label_paths.iter()
+            .map(|path| match detail.pointer(path) {
+                None | Some(Value::Null) => String::new(),
+                Some(Value::String(value)) => value.clone(),
+                Some(value) => value.to_string(),
+            })
+            .collect()
+    }
+
+    /// Value taken from the log detail, 1 if the rule has no value. None if the value is missing or not a number.
+    pub fn value(&self, detail: &Value) -> Option<f64> {
+        match &self.value_path {
+            None => Some(1.0),
+            Some(path) => match detail.pointer(path)? {
+                Value::Number(value) => value.as_f64(),
+                Value::String(value) => value.parse().ok(),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// The rules of the rules file, shared by all clones
+#[derive(Clone, Debug, Default)]
+pub struct Rules {
+    rules: Arc<Vec<Rule>>,
+}
+
+impl Rules {
+    /// Loads the rules file and registers the metrics of its rules
+    pub fn load(path: &str, common_labels: &HashMap<String, String>, histogram_buckets: &[f64], source_label: &Option<String>) -> Result<Rules, String> {
+        let content = std::fs::read(path).map_err(|e| format!("failed to read rules file {}: {}", path, e))?;
+        let rules_file: RulesFile = serde_json::from_slice(&content).map_err(|e| format!("invalid rules file {}: {}", path, e))?;
+
+        let rules = rules_file.rules.into_iter()
+            .map(|rule| Rule::new(rule, common_labels, histogram_buckets, source_label))
+            .collect::<Result<Vec<Rule>, String>>()?;
+        info!("Loaded {} rules from {}", rules.len(), path);
+
+        Ok(Rules { rules: Arc::new(rules) })
+    }
+
+    /// Rules matching the log type and level of a log line
+    pub fn matching<'a>(&'a self, logtype: &'a str, level: &'a str) -> impl Iterator<Item = &'a Rule> {
+        self.rules.iter().filter(move |rule| rule.matches(logtype, level))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Loads the rules from a rules file with the given content. The metric names of the rules must be unique
+    /// over all tests as the metrics are registered in the global registry.
+    pub(crate) fn load(name: &str, content: &str) -> Result<Rules, String> {
+        let path = std::env::temp_dir().join(format!("metrics-rules-{}-{}.json", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        let rules = Rules::load(path.to_str().unwrap(), &HashMap::new(), &[0.1, 1.0], &Some(String::from("source")));
+        std::fs::remove_file(&path).unwrap();
+        rules
+    }
+
+    #[test]
+    fn parses_rules() {
+        let rules = load("parse", r#"{"rules": [
+            {"name": "rules_parse_errors", "log_type": "http-log", "level": "error", "kind": "counter", "labels": {"status": "/http_info/status"}},
+            {"name": "rules_parse_times", "log_type": "http-log", "kind": "histogram", "value": "/operation/query_execution_time", "buckets": [0.5]},
+            {"name": "rules_parse_sockets", "help": "Open websockets", "log_type": "websocket-log", "kind": "gauge-inc"}
+        ]}"#).unwrap();
+
+        let kinds: Vec<RuleKind> = rules.matching("http-log", "error").map(|rule| rule.kind).collect();
+        assert_eq!(kinds, vec![RuleKind::Counter, RuleKind::Histogram]);
+        let kinds: Vec<RuleKind> = rules.matching("http-log", "info").map(|rule| rule.kind).collect();
+        assert_eq!(kinds, vec![RuleKind::Histogram]);
+        let kinds: Vec<RuleKind> = rules.matching("websocket-log", "info").map(|rule| rule.kind).collect();
+        assert_eq!(kinds, vec![RuleKind::GaugeInc]);
+        assert_eq!(rules.matching("query-log", "info").count(), 0);
+
+        let histogram = rules.matching("http-log", "info").next().unwrap();
+        match &histogram.metric {
+            RuleMetric::Histogram(histogram) => {
+                histogram.with_label_values(&["parse"]).observe(1.0);
+                assert_eq!(histogram.with_label_values(&["parse"]).get_sample_count(), 1);
+            }
+            metric => panic!("unexpected metric {:?}", metric),
+        }
+    }
+
+    #[test]
+    fn takes_labels_from_pointers() {
+        let rules = load("labels", r#"{"rules": [
+            {"name": "rules_labels_requests", "log_type": "http-log", "kind": "counter",
+             "labels": {"status": "/http_info/status", "url": "/http_info/url", "role": "/operation/user_vars/x-hasura-role", "missing": "/nope"}}
+        ]}"#).unwrap();
+        let rule = rules.matching("http-log", "info").next().unwrap();
+        let detail = json!({
+            "operation": {"user_vars": {"x-hasura-role": "user"}, "query_execution_time": 0.25},
+            "http_info": {"status": 200, "url": "/v1/graphql", "method": "POST"}
+        });
+        // the labels are ordered by name
+        assert_eq!(rule.label_values(&detail), vec!["", "user", "200", "/v1/graphql"]);
+        assert_eq!(rule.value(&detail), Some(1.0));
+    }
+
+    #[test]
+    fn takes_numbers_from_strings() {
+        let rules = load("values", r#"{"rules": [
+            {"name": "rules_values_size", "log_type": "http-log", "kind": "histogram", "value": "/operation/response_size"}
+        ]}"#).unwrap();
+        let rule = rules.matching("http-log", "info").next().unwrap();
+        assert_eq!(rule.value(&json!({"operation": {"response_size": 2048}})), Some(2048.0));
+        assert_eq!(rule.value(&json!({"operation": {"response_size": "0.5"}})), Some(0.5));
+        assert_eq!(rule.value(&json!({"operation": {"response_size": "large"}})), None);
+        assert_eq!(rule.value(&json!({"operation": {"response_size": null}})), None);
+        assert_eq!(rule.value(&json!({"operation": {}})), None);
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        let err = load("duplicate", r#"{"rules": [
+            {"name": "rules_duplicate_total", "log_type": "http-log", "kind": "counter"},
+            {"name": "rules_duplicate_total", "log_type": "query-log", "kind": "counter"}
+        ]}"#).unwrap_err();
+        assert!(err.starts_with("rule 'rules_duplicate_total': "), "{}", err);
+
+        let err = load("histogram", r#"{"rules": [
+            {"name": "rules_histogram_times", "log_type": "http-log", "kind": "histogram"}
+        ]}"#).unwrap_err();
+        assert_eq!(err, "rule 'rules_histogram_times': a histogram needs a value");
+
+        let err = load("name", r#"{"rules": [{"name": "rules-name", "log_type": "http-log", "kind": "counter"}]}"#).unwrap_err();
+        assert!(err.starts_with("rule 'rules-name': "), "{}", err);
+
+        let err = load("kind", r#"{"rules": [{"name": "rules_kind", "log_type": "http-log", "kind": "summary"}]}"#).unwrap_err();
+        assert!(err.starts_with("invalid rules file "), "{}", err);
+    }
+}
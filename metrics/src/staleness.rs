@@ -0,0 +1,50 @@
+use chrono::Utc;
+use log::{info, warn};
+use prometheus::core::Collector;
+use tokio::{sync::watch, time};
+
+use crate::{Configuration, Telemetry};
+
+/// Interval of the staleness check in milliseconds
+const STALENESS_CHECK_INTERVAL: u64 = 1000;
+
+/// Wall-clock time the last log line of any source was processed, in seconds since the epoch
+fn last_processed(metric_obj: &Telemetry) -> Option<f64> {
+    metric_obj.LOG_LAST_PROCESSED.collect().iter()
+        .flat_map(|family| family.get_metric())
+        .map(|metric| metric.get_gauge().get_value())
+        .reduce(f64::max)
+}
+
+/// Flips the stale gauge when no log line was processed for longer than the staleness threshold,
+/// so a stuck log stream can be told apart from a quiet one. Does nothing with a threshold of 0.
+pub(crate) async fn run_staleness_check(cfg: &Configuration, metric_obj: &Telemetry, mut termination_rx: watch::Receiver<()>) -> std::io::Result<()> {
+    if cfg.stale_threshold == 0 {
+        return Ok(());
+    }
+
+    let threshold = cfg.stale_threshold as f64 / 1000.0;
+    let started = Utc::now().timestamp_millis() as f64 / 1000.0;
+    let mut interval = time::interval(time::Duration::from_millis(STALENESS_CHECK_INTERVAL));
+
+    loop {
+        tokio::select! {
+            biased;
+            _ = termination_rx.changed() => return Ok(()),
+
+            _ = interval.tick() => {
+                let now = Utc::now().timestamp_millis() as f64 / 1000.0;
+                // before the first line is processed the stream is stale once the threshold passed since the start
+                let last = last_processed(metric_obj).unwrap_or(started).max(started);
+                let stale = now - last > threshold;
+
+                if stale && metric_obj.LOG_STREAM_STALE.get() == 0 {
+                    warn!("No log line processed for more than {} ms, the log stream is stale", cfg.stale_threshold);
+                } else if !stale && metric_obj.LOG_STREAM_STALE.get() == 1 {
+                    info!("Log lines are processed again, the log stream is no longer stale");
+                }
+                metric_obj.LOG_STREAM_STALE.set(stale as i64);
+            },
+        }
+    }
+}
@@ -0,0 +1,353 @@
+use log::{debug, info, warn};
+use std::io::{Error, ErrorKind, Result};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, BufReader},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::watch,
+};
+
+use crate::pipeline::Pipeline;
+use crate::{Configuration, Telemetry};
+
+/// Frames larger than this are dropped, hasura log lines with big queries easily exceed the
+/// 2048 bytes syslog guarantees, so this is generous on purpose
+pub(crate) const MAX_FRAME_SIZE: usize = 1024 * 1024;
+
+/// Source label value of lines received via syslog
+const SYSLOG_SOURCE: &str = "syslog";
+
+/// Maximum length of the length prefix of an octet counted frame, including the space
+const MAX_LENGTH_PREFIX: u64 = 21;
+
+/// Maximum payload of a single datagram (UDP or unix socket)
+pub(crate) const MAX_DATAGRAM_SIZE: usize = 65535;
+
+pub(crate) enum Frame {
+    Message(Vec<u8>),
+    Invalid,
+}
+
+pub(crate) async fn run_syslog_receiver(cfg: &Configuration, metric_obj: &Telemetry, pipeline: &Pipeline, termination_rx: watch::Receiver<()>) -> Result<()> {
+    tokio::try_join!(
+        async {
+            match &cfg.syslog_tcp_addr {
+                Some(addr) => listen_tcp(addr, metric_obj, pipeline, termination_rx.clone()).await,
+                None => Ok(()),
+            }
+        },
+        async {
+            match &cfg.syslog_udp_addr {
+                Some(addr) => listen_udp(addr, metric_obj, pipeline, termination_rx.clone()).await,
+                None => Ok(()),
+            }
+        }
+    )?;
+    Ok(())
+}
+
+async fn listen_tcp(addr: &str, metric_obj: &Telemetry, pipeline: &Pipeline, mut termination_rx: watch::Receiver<()>) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Listening for syslog messages on tcp://{}", addr);
+
+    loop {
+        tokio::select! {
+            biased;
+            _ = termination_rx.changed() => return Ok(()),
+
+            result = listener.accept() => {
+                match result {
+                    Ok((stream, peer)) => {
+                        debug!("Accepted syslog connection from {}", peer);
+                        tokio::spawn(handle_tcp_connection(stream, peer, metric_obj.clone(), pipeline.clone(), termination_rx.clone()));
+                    }
+                    Err(e) => {
+                        warn!("Failed to accept syslog connection: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn handle_tcp_connection(stream: TcpStream, peer: SocketAddr, metric_obj: Telemetry, pipeline: Pipeline, mut termination_rx: watch::Receiver<()>) {
+    let peer_ip = peer.ip().to_string();
+    metric_obj.guarded(&metric_obj.SYSLOG_CONNECTIONS, &[peer_ip.as_str()]).inc();
+    metric_obj.SYSLOG_CONNECTIONS_ACTIVE.inc();
+
+    let mut reader = BufReader::new(stream);
+    loop {
+        tokio::select! {
+            biased;
+            _ = termination_rx.changed() => break,
+
+            frame = read_tcp_frame(&mut reader) => {
+                match frame {
+                    Ok(Some(Frame::Message(bytes))) => process_frame(&bytes, "tcp", &metric_obj, &pipeline).await,
+                    Ok(Some(Frame::Invalid)) => {
+                        metric_obj.SYSLOG_DROPPED_FRAMES.with_label_values(&["tcp"]).inc();
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        warn!("Error reading syslog connection from {}: {}", peer, e);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    debug!("Syslog connection from {} closed", peer);
+    metric_obj.SYSLOG_CONNECTIONS_ACTIVE.dec();
+}
+
+/// Reads a single frame from a syslog TCP stream, supporting both octet counting
+/// (`<len> <msg>`, RFC 6587 3.4.1) and non-transparent framing terminated by a new line.
+async fn read_tcp_frame<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<Frame>> {
+    let first = match reader.fill_buf().await?.first() {
+        Some(byte) => *byte,
+        None => return Ok(None),
+    };
+
+    if first.is_ascii_digit() {
+        let mut buf = Vec::new();
+        // a peer which never sends the space can't make the buffer grow unbounded
+        (&mut *reader).take(MAX_LENGTH_PREFIX).read_until(b' ', &mut buf).await?;
+        if buf.last() != Some(&b' ') {
+            return Err(Error::new(ErrorKind::InvalidData, "length prefix of octet counted frame too long"));
+        }
+        let len = match std::str::from_utf8(&buf).ok().and_then(|v| v.trim_end().parse::<usize>().ok()) {
+            Some(len) => len,
+            None => return Ok(Some(Frame::Invalid)),
+        };
+
+        if len > MAX_FRAME_SIZE {
+            // skip the oversized message to stay in sync with the framing
+            tokio::io::copy(&mut (&mut *reader).take(len as u64), &mut tokio::io::sink()).await?;
+            return Ok(Some(Frame::Invalid));
+        }
+
+        let mut message = vec![0; len];
+        reader.read_exact(&mut message).await?;
+        Ok(Some(Frame::Message(message)))
+    } else {
+        read_line_frame(reader).await
+    }
+}
+
+/// Reads a frame terminated by a new line, lines larger than `MAX_FRAME_SIZE` are skipped
+/// up to the next new line and returned as invalid frame.
+pub(crate) async fn read_line_frame<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<Frame>> {
+    let mut buf = Vec::new();
+    if (&mut *reader).take(MAX_FRAME_SIZE as u64).read_until(b'\n', &mut buf).await? == 0 {
+        return Ok(None);
+    }
+    if buf.last() != Some(&b'\n') && buf.len() >= MAX_FRAME_SIZE {
+        // discard the rest of the oversized line without buffering it
+        loop {
+            let (done, used) = {
+                let available = reader.fill_buf().await?;
+                match available.iter().position(|byte| *byte == b'\n') {
+                    Some(idx) => (true, idx + 1),
+                    None => (available.is_empty(), available.len()),
+                }
+            };
+            reader.consume(used);
+            if done {
+                break;
+            }
+        }
+        return Ok(Some(Frame::Invalid));
+    }
+    Ok(Some(Frame::Message(buf)))
+}
+
+async fn listen_udp(addr: &str, metric_obj: &Telemetry, pipeline: &Pipeline, mut termination_rx: watch::Receiver<()>) -> Result<()> {
+    let socket = UdpSocket::bind(addr).await?;
+    info!("Listening for syslog messages on udp://{}", addr);
+
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+    loop {
+        tokio::select! {
+            biased;
+            _ = termination_rx.changed() => return Ok(()),
+
+            result = socket.recv_from(&mut buf) => {
+                match result {
+                    Ok((len, _peer)) => process_frame(&buf[..len], "udp", metric_obj, pipeline).await,
+                    Err(e) => {
+                        warn!("Failed to receive syslog datagram: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn process_frame(frame: &[u8], protocol: &str, metric_obj: &Telemetry, pipeline: &Pipeline) {
+    let payload = std::str::from_utf8(frame)
+        .ok()
+        .and_then(|v| strip_envelope(v.trim_end_matches(['\r', '\n'])));
+
+    match payload {
+        Some(line) => {
+            debug!("Reading line from syslog");
+            pipeline.submit(line.to_string(), &Arc::from(SYSLOG_SOURCE)).await;
+        }
+        None => {
+            warn!("Dropping invalid syslog frame received via {}", protocol);
+            metric_obj.SYSLOG_DROPPED_FRAMES.with_label_values(&[protocol]).inc();
+        }
+    }
+}
+
+/// Removes the RFC 5424 or RFC 3164 header of a syslog message and returns the payload
+fn strip_envelope(frame: &str) -> Option<&str> {
+    let rest = frame.strip_prefix('<')?;
+    let end = rest.find('>')?;
+    if end == 0 || end > 3 || !rest[..end].bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let rest = &rest[end + 1..];
+    match rest.strip_prefix("1 ") {
+        Some(header) => strip_rfc5424(header),
+        None => strip_rfc3164(rest),
+    }
+}
+
+/// `TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA [MSG]`
+fn strip_rfc5424(header: &str) -> Option<&str> {
+    let mut rest = header;
+    for _ in 0..5 {
+        let idx = rest.find(' ')?;
+        rest = &rest[idx + 1..];
+    }
+
+    let rest = match rest.strip_prefix('-') {
+        Some(rest) => rest,
+        None => skip_structured_data(rest)?,
+    };
+    let msg = rest.strip_prefix(' ').unwrap_or(rest);
+    Some(msg.strip_prefix('\u{feff}').unwrap_or(msg))
+}
+
+fn skip_structured_data(sd: &str) -> Option<&str> {
+    let mut in_element = false;
+    let mut in_value = false;
+    let mut escaped = false;
+
+    for (i, c) in sd.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' if in_value => escaped = true,
+            '"' if in_element => in_value = !in_value,
+            '[' if !in_element => in_element = true,
+            ']' if in_element && !in_value => in_element = false,
+            ' ' if !in_element => return Some(&sd[i..]),
+            _ if !in_element => return None,
+            _ => (),
+        }
+    }
+
+    if in_element { None } else { Some("") }
+}
+
+/// `Mmm dd hh:mm:ss HOSTNAME TAG: MSG`, relays are not very strict about this format, so the
+/// payload starts after the tag or at the first JSON object, whatever comes first
+fn strip_rfc3164(rest: &str) -> Option<&str> {
+    let rest = rest.get(16..)?;
+    let json_start = rest.find('{');
+    let tag_end = rest.find(": ").map(|idx| idx + 2);
+
+    match (json_start, tag_end) {
+        (Some(json), Some(tag)) => Some(&rest[json.min(tag)..]),
+        (Some(json), None) => Some(&rest[json..]),
+        (None, Some(tag)) => Some(&rest[tag..]),
+        (None, None) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn frames(input: &[u8]) -> Vec<Option<Vec<u8>>> {
+        let mut reader = input;
+        let mut frames = Vec::new();
+        while let Some(frame) = read_tcp_frame(&mut reader).await.unwrap() {
+            frames.push(match frame {
+                Frame::Message(message) => Some(message),
+                Frame::Invalid => None,
+            });
+        }
+        frames
+    }
+
+    #[tokio::test]
+    async fn reads_octet_counted_frames() {
+        assert_eq!(frames(b"5 hello3 abc").await, vec![Some(b"hello".to_vec()), Some(b"abc".to_vec())]);
+        // a new line inside the message belongs to the message
+        assert_eq!(frames(b"3 a\nb").await, vec![Some(b"a\nb".to_vec())]);
+    }
+
+    #[tokio::test]
+    async fn reads_new_line_terminated_frames() {
+        assert_eq!(frames(b"<14>first\n<14>second").await, vec![Some(b"<14>first\n".to_vec()), Some(b"<14>second".to_vec())]);
+    }
+
+    #[tokio::test]
+    async fn skips_oversized_frames() {
+        let mut input = format!("{} ", MAX_FRAME_SIZE + 1).into_bytes();
+        input.extend(vec![b'x'; MAX_FRAME_SIZE + 1]);
+        input.extend(b"2 ok");
+        assert_eq!(frames(&input).await, vec![None, Some(b"ok".to_vec())]);
+
+        let mut input = vec![b'x'; MAX_FRAME_SIZE + 10];
+        input.extend(b"\nnext\n");
+        assert_eq!(frames(&input).await, vec![None, Some(b"next\n".to_vec())]);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_length_prefix() {
+        assert_eq!(frames(b"12a hello").await, vec![None, Some(b"hello".to_vec())]);
+
+        let input = vec![b'1'; MAX_LENGTH_PREFIX as usize + 1];
+        let err = read_tcp_frame(&mut input.as_slice()).await.err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn strips_rfc5424_envelope() {
+        assert_eq!(strip_envelope("<14>1 2024-01-01T00:00:00Z host hasura 1 - - {\"level\":\"info\"}"), Some("{\"level\":\"info\"}"));
+        assert_eq!(strip_envelope("<14>1 2024-01-01T00:00:00Z host hasura 1 - - \u{feff}{}"), Some("{}"));
+        assert_eq!(
+            strip_envelope(r#"<14>1 2024-01-01T00:00:00Z host hasura 1 ID [a@1 x="v] \"q\""][b@1] {}"#),
+            Some("{}"),
+        );
+        assert_eq!(strip_envelope("<14>1 2024-01-01T00:00:00Z host hasura 1 - -"), Some(""));
+        assert_eq!(strip_envelope("<14>1 2024-01-01T00:00:00Z host hasura 1 - [unterminated {}"), None);
+        assert_eq!(strip_envelope("<14>1 2024-01-01T00:00:00Z host hasura 1 - x {}"), None);
+    }
+
+    #[test]
+    fn strips_rfc3164_envelope() {
+        assert_eq!(strip_envelope("<14>Jan  1 00:00:00 host hasura[1]: {\"level\":\"info\"}"), Some("{\"level\":\"info\"}"));
+        assert_eq!(strip_envelope("<14>Jan  1 00:00:00 host {\"detail\":{\"a\": 1}}"), Some("{\"detail\":{\"a\": 1}}"));
+        assert_eq!(strip_envelope("<14>Jan  1 00:00:00 host hasura: plain"), Some("plain"));
+        assert_eq!(strip_envelope("<14>Jan  1 00:00:00 host no payload"), None);
+        assert_eq!(strip_envelope("<14>short"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_priority() {
+        assert_eq!(strip_envelope("{\"level\":\"info\"}"), None);
+        assert_eq!(strip_envelope("<>1 - - - - - - {}"), None);
+        assert_eq!(strip_envelope("<1234>1 - - - - - - {}"), None);
+        assert_eq!(strip_envelope("<1a>1 - - - - - - {}"), None);
+    }
+}
@@ -1,6 +1,13 @@
 use std::collections::HashMap;
-use prometheus::{HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts};
-use prometheus::{register_int_counter_vec, register_int_counter, register_int_gauge, register_int_gauge_vec, register_histogram_vec};
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, RwLock};
+use prometheus::core::{Collector, MetricVec, MetricVecBuilder};
+use prometheus::{GaugeVec, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts};
+use prometheus::{register_gauge_vec, register_int_counter, register_int_counter_vec, register_int_gauge, register_int_gauge_vec, register_histogram_vec};
+
+use crate::cardinality::{CardinalityGuard, CardinalityLimits};
+use crate::rules::Rules;
+use crate::urlnormalize::UrlNormalizer;
 
 #[allow(non_snake_case)]
 #[derive(Clone, Debug)]
@@ -27,17 +34,77 @@ pub struct Telemetry {
     pub SCHEDULED_EVENTS_SUCCESSFUL: IntGauge,
     pub SCHEDULED_EVENTS_FAILED: IntGauge,
 
-    pub ACTIVE_WEBSOCKET: IntGauge,
-    pub ACTIVE_WEBSOCKET_OPERATIONS: IntGauge,
+    pub ACTIVE_WEBSOCKET: IntGaugeVec,
+    pub ACTIVE_WEBSOCKET_OPERATIONS: IntGaugeVec,
     pub WEBSOCKET_OPERATIONS: IntCounterVec,
 
-    pub LOG_LINES_COUNTER_TOTAL: IntCounter,
+    pub LOG_LINES_COUNTER_TOTAL: IntCounterVec,
     pub LOG_LINES_COUNTER: IntCounterVec,
+    pub LOG_LEVEL_LINES: IntCounterVec,
+    pub LOG_ERROR_CODES: IntCounterVec,
+    pub LOG_FILE_ROTATIONS: IntCounterVec,
+    pub LOG_FOLLOW_MODE: IntGaugeVec,
+    pub LOG_PROCESSING_DELAY: HistogramVec,
+    pub LOG_LAST_TIMESTAMP: GaugeVec,
+    pub LOG_LAST_PROCESSED: GaugeVec,
+    pub LOG_STREAM_STALE: IntGauge,
+
+    pub SYSLOG_CONNECTIONS: IntCounterVec,
+    pub SYSLOG_CONNECTIONS_ACTIVE: IntGauge,
+    pub SYSLOG_DROPPED_FRAMES: IntCounterVec,
+
+    pub UNIX_SOCKET_CONNECTIONS: IntCounterVec,
+    pub UNIX_SOCKET_CONNECTIONS_ACTIVE: IntGauge,
+    pub UNIX_SOCKET_LISTENING: IntGaugeVec,
+    pub UNIX_SOCKET_DATAGRAMS: IntCounterVec,
+    pub UNIX_SOCKET_DROPPED_LINES: IntCounterVec,
+
+    pub INGEST_REQUESTS: IntCounterVec,
+    pub INGEST_LINES: IntCounterVec,
+
+    pub PIPELINE_QUEUE_DEPTH: IntGauge,
+    pub PIPELINE_DROPPED_LINES: IntCounter,
+    pub PIPELINE_STAGE_SECONDS: HistogramVec,
 
     pub REQUEST_COUNTER: IntCounterVec,
     pub REQUEST_QUERY_COUNTER: IntCounterVec,
     pub QUERY_EXECUTION_TIMES: HistogramVec,
-
+    pub REQUEST_DURATION: HistogramVec,
+    pub RESPONSE_SIZE: HistogramVec,
+    pub ROOT_FIELD_REQUESTS: IntCounterVec,
+    pub REQUEST_BATCH_SIZE: HistogramVec,
+
+    pub GENERATED_SQL_STATEMENTS: IntCounterVec,
+    pub GENERATED_SQL_SIZE: HistogramVec,
+
+    pub WEBHOOK_REQUESTS: IntCounterVec,
+    pub WEBHOOK_LATENCY: HistogramVec,
+
+    pub EVENT_TRIGGER_DELIVERIES: IntCounterVec,
+    pub EVENT_TRIGGER_DELIVERY_LATENCY: HistogramVec,
+
+    pub SCHEDULED_TRIGGER_INVOCATIONS: IntCounterVec,
+    pub SCHEDULED_TRIGGER_INVOCATION_LATENCY: HistogramVec,
+
+    pub LIVEQUERY_POLLS: IntCounterVec,
+    pub LIVEQUERY_COHORTS: IntGaugeVec,
+    pub LIVEQUERY_POLL_TIME: HistogramVec,
+    pub LIVEQUERY_SNAPSHOT_TIME: HistogramVec,
+    pub LIVEQUERY_DB_EXECUTION_TIME: HistogramVec,
+    pub LIVEQUERY_PUSH_TIME: HistogramVec,
+
+    pub CARDINALITY_FOLDED: IntCounterVec,
+
+    /// Metrics derived from the log by the rules of the rules file
+    pub rules: Rules,
+    /// Normalization of the url label of the requests
+    pub url_normalizer: UrlNormalizer,
+
+    source_label_enabled: bool,
+    request_labels: RequestLabels,
+    cardinality_guard: CardinalityGuard,
+    /// Latest log timestamp in milliseconds by the label values of `LOG_LAST_TIMESTAMP`
+    last_timestamps: Arc<RwLock<HashMap<Vec<String>, Arc<AtomicI64>>>>,
 }
 
 pub enum MetricOption<'a> {
@@ -45,8 +112,116 @@ pub enum MetricOption<'a> {
     IntGauge(&'a IntGauge)
 }
 
+/// Response size buckets in bytes used if none are configured, from 1KiB to 64MiB
+const DEFAULT_RESPONSE_SIZE_BUCKETS: [f64; 9] = [1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0, 16777216.0, 67108864.0];
+
+/// Label value that replaces label values which are not allowed
+pub const OTHER_LABEL_VALUE: &str = "__other__";
+
+/// Optional labels of the http request metrics, identifying who sent the request
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RequestLabels {
+    pub role: bool,
+    pub client: bool,
+}
+
+/// Label names of the metrics derived from the log, the source label must not be one of them
+pub const LOG_LABEL_NAMES: &[&str] = &[
+    "logtype", "level", "code", "mode", "rotation", "operation", "operation_type", "root_field", "error",
+    "status", "url", "method", "kind", "parameterized_query_hash", "trigger_name", "role", "client",
+];
+
+/// Adds the source label to the label names of metrics derived from the log, if configured
+pub(crate) fn log_label_names<'a>(labels: &[&'a str], source_label: &'a Option<String>) -> Vec<&'a str> {
+    let mut names = labels.to_vec();
+    if let Some(source_label) = source_label {
+        names.push(source_label.as_str());
+    }
+    names
+}
+
+/// Adds the enabled request labels and the source label to the label names of the http request metrics
+fn request_label_names<'a>(labels: &[&'a str], request_labels: RequestLabels, source_label: &'a Option<String>) -> Vec<&'a str> {
+    let mut names = labels.to_vec();
+    if request_labels.role {
+        names.push("role");
+    }
+    if request_labels.client {
+        names.push("client");
+    }
+    log_label_names(&names, source_label)
+}
+
 impl Telemetry {
-    pub fn new(common_labels: HashMap<String, String>, histogram_buckets: Vec<f64>) -> Telemetry {
+    /// Label values for metrics derived from the log, adds the log source if a source label is configured
+    pub fn log_labels<'a>(&self, source: &'a str, labels: &[&'a str]) -> Vec<&'a str> {
+        let mut values = labels.to_vec();
+        if self.source_label_enabled {
+            values.push(source);
+        }
+        values
+    }
+
+    /// Label values for the http request metrics, adds the role and client if these labels are enabled
+    pub fn request_labels<'a>(&self, source: &'a str, labels: &[&'a str], role: &'a str, client: &'a str) -> Vec<&'a str> {
+        let mut values = labels.to_vec();
+        if self.request_labels.role {
+            values.push(role);
+        }
+        if self.request_labels.client {
+            values.push(client);
+        }
+        self.log_labels(source, &values)
+    }
+
+    /// Metric of a log derived metric vector with the given label values. Label values beyond
+    /// the cardinality limit of the metric are folded into `__other__`.
+    pub fn guarded<T: MetricVecBuilder>(&self, metric: &MetricVec<T>, values: &[&str]) -> T::M {
+        let desc = metric.desc()[0];
+        let allowed = self.cardinality_guard.check(&desc.fq_name, values);
+        if allowed.iter().all(|allowed| *allowed) {
+            return metric.with_label_values(values);
+        }
+
+        let values: Vec<&str> = values.iter().zip(&allowed).zip(&desc.variable_labels)
+            .map(|((value, allowed), label)| {
+                if *allowed {
+                    *value
+                } else {
+                    self.CARDINALITY_FOLDED.with_label_values(&[desc.fq_name.as_str(), label.as_str()]).inc();
+                    OTHER_LABEL_VALUE
+                }
+            })
+            .collect();
+        metric.with_label_values(&values)
+    }
+
+    /// Sets `LOG_LAST_TIMESTAMP` to the timestamp if it is later than the timestamps seen before.
+    /// The workers process lines out of order, the latest timestamp is kept in an atomic and every
+    /// worker that raised it sets the gauge until the gauge holds the latest timestamp.
+    pub fn set_last_timestamp(&self, values: &[&str], timestamp_millis: i64) {
+        let key: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+        let existing = self.last_timestamps.read().unwrap().get(&key).cloned();
+        let last = match existing {
+            Some(last) => last,
+            None => self.last_timestamps.write().unwrap().entry(key).or_insert_with(|| Arc::new(AtomicI64::new(i64::MIN))).clone(),
+        };
+        if last.fetch_max(timestamp_millis, Ordering::SeqCst) >= timestamp_millis {
+            return;
+        }
+
+        let gauge = self.guarded(&self.LOG_LAST_TIMESTAMP, values);
+        loop {
+            let latest = last.load(Ordering::SeqCst);
+            gauge.set(latest as f64 / 1000.0);
+            // another worker raised the timestamp and may have set the gauge before this one
+            if last.load(Ordering::SeqCst) == latest {
+                break;
+            }
+        }
+    }
+
+    pub fn new(common_labels: HashMap<String, String>, histogram_buckets: Vec<f64>, request_duration_buckets: Vec<f64>, response_size_buckets: Vec<f64>, request_labels: RequestLabels, cardinality_limits: CardinalityLimits, source_label: Option<String>) -> Telemetry {
 
         let errors_total_opts = Opts {
             namespace: String::from(""),
@@ -231,6 +406,197 @@ impl Telemetry {
             variable_labels : vec![]
         };
 
+        let log_level_lines_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_log_level_lines"),
+            help : String::from("Number of log lines processed by log type and level"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+
+        let log_error_codes_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_log_error_codes"),
+            help : String::from("Number of error codes in the log lines by log type and code"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+
+        let log_file_rotations_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_log_file_rotations"),
+            help : String::from("Number of detected log file rotations. rotation is 'replaced' if the file was removed or renamed and 'truncated' if it was truncated in place"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+
+        let log_follow_mode_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_log_follow_mode"),
+            help : String::from("Number of followed log files by follow mode. mode is 'notify' if file system notifications are used and 'poll' otherwise"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let log_processing_delay_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_log_processing_delay_seconds"),
+            help : String::from("Delay between the timestamp of a log line and the time it was processed"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let log_processing_delay_histogram_opts = HistogramOpts {
+            common_opts: log_processing_delay_opts,
+            buckets: vec![0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0]
+        };
+
+        let log_last_timestamp_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_log_last_timestamp_seconds"),
+            help : String::from("Timestamp of the latest log line seen per log type, in seconds since the epoch"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+
+        let log_last_processed_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_log_last_processed_timestamp_seconds"),
+            help : String::from("Wall-clock time the last log line was processed, in seconds since the epoch"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+
+        let log_stream_stale_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_log_stream_stale"),
+            help : String::from("1 if no log line was processed for longer than the staleness threshold, 0 otherwise"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+
+
+        let syslog_connections_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_syslog_connections"),
+            help : String::from("Number of syslog TCP connections accepted per peer address"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let syslog_connections_active_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_syslog_connections_active"),
+            help : String::from("Number of open syslog TCP connections"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let syslog_dropped_frames_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_syslog_dropped_frames"),
+            help : String::from("Number of syslog frames dropped because they were malformed or too large, by protocol (tcp, udp)"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+
+
+        let unix_socket_connections_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_unix_socket_connections"),
+            help : String::from("Number of unix socket connection events (opened, closed, failed)"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let unix_socket_connections_active_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_unix_socket_connections_active"),
+            help : String::from("Number of open unix socket connections"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let unix_socket_listening_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_unix_socket_listening"),
+            help : String::from("1 while the unix socket is bound, by socket type (stream, datagram)"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let unix_socket_datagrams_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_unix_socket_datagrams"),
+            help : String::from("Number of datagrams on the unix datagram socket by result (received, failed)"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let unix_socket_dropped_lines_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_unix_socket_dropped_lines"),
+            help : String::from("Number of lines received via a unix socket dropped because they were too large or not valid UTF-8, by socket type (stream, datagram)"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+
+
+        let ingest_requests_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_ingest_requests"),
+            help : String::from("Number of requests to the /ingest endpoint by result (accepted, rejected)"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let ingest_lines_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_ingest_lines"),
+            help : String::from("Number of log lines received via the /ingest endpoint by result (accepted, rejected)"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+
+
+        let pipeline_queue_depth_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_pipeline_queue_depth"),
+            help : String::from("Number of log lines waiting in the queue to be processed"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let pipeline_dropped_lines_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_pipeline_dropped_lines"),
+            help : String::from("Number of log lines dropped because the queue was full"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let pipeline_stage_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_pipeline_stage_seconds"),
+            help : String::from("Time a log line spent in a stage of the processing pipeline (queue, process)"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let pipeline_stage_histogram_opts = HistogramOpts {
+            common_opts: pipeline_stage_opts,
+            buckets: vec![0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0]
+        };
+
 
         let request_counter_opts = Opts {
             namespace: String::from(""),
@@ -258,11 +624,214 @@ impl Telemetry {
         };
         let query_execution_seconds_histogram_opts = HistogramOpts {
             common_opts: query_execution_seconds_opts,
-            buckets: histogram_buckets
+            buckets: histogram_buckets.clone()
+        };
+        let request_duration_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_request_duration_seconds"),
+            help : String::from("Request time (reading the request and executing it) by operation and http status. Unnnamed operations are ''"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let request_duration_histogram_opts = HistogramOpts {
+            common_opts: request_duration_opts,
+            buckets: request_duration_buckets
+        };
+        let response_size_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_response_size_bytes"),
+            help : String::from("Response size in bytes by operation and http status. Unnnamed operations are ''"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let response_size_histogram_opts = HistogramOpts {
+            common_opts: response_size_opts,
+            buckets: if response_size_buckets.is_empty() { DEFAULT_RESPONSE_SIZE_BUCKETS.to_vec() } else { response_size_buckets }
+        };
+        let root_field_requests_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_root_field_requests"),
+            help : String::from("Number of graphql requests per operation type (query, mutation, subscription) and root field. On success, error is '', otherwise it's the error code"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let request_batch_size_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_request_batch_size"),
+            help : String::from("Number of operations in batched graphql requests"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let request_batch_size_histogram_opts = HistogramOpts {
+            common_opts: request_batch_size_opts,
+            buckets: vec![2.0, 5.0, 10.0, 20.0, 50.0, 100.0]
+        };
+
+        let generated_sql_statements_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_generated_sql_statements"),
+            help : String::from("Number of SQL statements generated for graphql requests per operation and root field. Unnnamed operations are ''"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let generated_sql_size_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_generated_sql_size_bytes"),
+            help : String::from("Size of the SQL statements generated for graphql requests per operation and root field. Unnnamed operations are ''"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let generated_sql_size_histogram_opts = HistogramOpts {
+            common_opts: generated_sql_size_opts,
+            buckets: vec![256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0]
+        };
+
+        let webhook_requests_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_webhook_requests"),
+            help : String::from("Number of webhook requests (actions, remote schemas, auth webhook) by url, method, status class and error type"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let webhook_latency_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_webhook_latency_seconds"),
+            help : String::from("Webhook request latency by url and status class"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let webhook_latency_histogram_opts = HistogramOpts {
+            common_opts: webhook_latency_opts,
+            buckets: histogram_buckets.clone()
+        };
+
+        let event_trigger_deliveries_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_event_trigger_deliveries"),
+            help : String::from("Number of event trigger webhook deliveries by trigger name and response status, 'error' if no response was received"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let event_trigger_delivery_latency_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_event_trigger_delivery_seconds"),
+            help : String::from("Event trigger webhook delivery latency by trigger name"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let event_trigger_delivery_latency_histogram_opts = HistogramOpts {
+            common_opts: event_trigger_delivery_latency_opts,
+            buckets: histogram_buckets.clone()
+        };
+
+        let scheduled_trigger_invocations_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_scheduled_trigger_invocations"),
+            help : String::from("Number of cron trigger and one-off scheduled event invocations by trigger name and response status, 'error' if no response was received"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let scheduled_trigger_invocation_latency_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_scheduled_trigger_invocation_seconds"),
+            help : String::from("Cron trigger and one-off scheduled event invocation latency by trigger name"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let scheduled_trigger_invocation_latency_histogram_opts = HistogramOpts {
+            common_opts: scheduled_trigger_invocation_latency_opts,
+            buckets: histogram_buckets.clone()
+        };
+
+        let livequery_polls_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_livequery_polls"),
+            help : String::from("Number of subscription poller runs by subscription kind and parameterized query hash"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let livequery_cohorts_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_livequery_cohorts"),
+            help : String::from("Number of cohorts in the last run of the subscription poller by subscription kind and parameterized query hash"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let livequery_poll_time_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_livequery_poll_seconds"),
+            help : String::from("Total time of a subscription poller run"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let livequery_poll_time_histogram_opts = HistogramOpts {
+            common_opts: livequery_poll_time_opts,
+            buckets: histogram_buckets.clone()
+        };
+        let livequery_snapshot_time_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_livequery_snapshot_seconds"),
+            help : String::from("Time the subscription poller took to snapshot the cohorts before running the query"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let livequery_snapshot_time_histogram_opts = HistogramOpts {
+            common_opts: livequery_snapshot_time_opts,
+            buckets: histogram_buckets.clone()
+        };
+        let livequery_db_execution_time_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_livequery_db_execution_seconds"),
+            help : String::from("Database execution time of a subscription poller batch"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let livequery_db_execution_time_histogram_opts = HistogramOpts {
+            common_opts: livequery_db_execution_time_opts,
+            buckets: histogram_buckets.clone()
+        };
+        let livequery_push_time_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_livequery_push_seconds"),
+            help : String::from("Time the subscription poller took to push the results of a batch to the subscribers"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
+        };
+        let livequery_push_time_histogram_opts = HistogramOpts {
+            common_opts: livequery_push_time_opts,
+            buckets: histogram_buckets.clone()
+        };
+
+
+        let cardinality_folded_opts = Opts {
+            namespace: String::from(""),
+            subsystem: String::from(""),
+            name : String::from("hasura_cardinality_folded"),
+            help : String::from("Number of observations whose label value was folded into '__other__' because the label reached its cardinality limit"),
+            const_labels : common_labels.clone(),
+            variable_labels : vec![]
         };
 
 
-        Telemetry {
+        let telemetry = Telemetry {
             ERRORS_TOTAL : register_int_counter_vec!(errors_total_opts,&["collector"]).unwrap(),
 
             CRON_TRIGGER_PENDING: register_int_gauge_vec!(cron_trigger_pending_opts,&["trigger_name"]).unwrap(),
@@ -285,17 +854,127 @@ impl Telemetry {
             SCHEDULED_EVENTS_SUCCESSFUL: register_int_gauge!(scheduled_events_successful_opts).unwrap(),
             SCHEDULED_EVENTS_FAILED: register_int_gauge!(scheduled_events_failed_opts).unwrap(),
 
-            ACTIVE_WEBSOCKET: register_int_gauge!(active_websockets_opts).unwrap(),
-            ACTIVE_WEBSOCKET_OPERATIONS: register_int_gauge!(active_websockets_operations_opts).unwrap(),
-            WEBSOCKET_OPERATIONS: register_int_counter_vec!(websockets_operations_opts,&["operation", "error"]).unwrap(),
+            ACTIVE_WEBSOCKET: register_int_gauge_vec!(active_websockets_opts,&log_label_names(&[], &source_label)).unwrap(),
+            ACTIVE_WEBSOCKET_OPERATIONS: register_int_gauge_vec!(active_websockets_operations_opts,&log_label_names(&[], &source_label)).unwrap(),
+            WEBSOCKET_OPERATIONS: register_int_counter_vec!(websockets_operations_opts,&log_label_names(&["operation", "error"], &source_label)).unwrap(),
+
+            LOG_LINES_COUNTER_TOTAL: register_int_counter_vec!(log_lines_counter_total_opts,&log_label_names(&[], &source_label)).unwrap(),
+            LOG_LINES_COUNTER: register_int_counter_vec!(log_lines_counter_opts,&log_label_names(&["logtype"], &source_label)).unwrap(),
+            LOG_LEVEL_LINES: register_int_counter_vec!(log_level_lines_opts,&log_label_names(&["logtype", "level"], &source_label)).unwrap(),
+            LOG_ERROR_CODES: register_int_counter_vec!(log_error_codes_opts,&log_label_names(&["logtype", "code"], &source_label)).unwrap(),
+            LOG_FILE_ROTATIONS: register_int_counter_vec!(log_file_rotations_opts,&log_label_names(&["rotation"], &source_label)).unwrap(),
+            LOG_FOLLOW_MODE: register_int_gauge_vec!(log_follow_mode_opts,&log_label_names(&["mode"], &source_label)).unwrap(),
+            LOG_PROCESSING_DELAY: register_histogram_vec!(log_processing_delay_histogram_opts,&log_label_names(&[], &source_label)).unwrap(),
+            LOG_LAST_TIMESTAMP: register_gauge_vec!(log_last_timestamp_opts,&log_label_names(&["logtype"], &source_label)).unwrap(),
+            LOG_LAST_PROCESSED: register_gauge_vec!(log_last_processed_opts,&log_label_names(&[], &source_label)).unwrap(),
+            LOG_STREAM_STALE: register_int_gauge!(log_stream_stale_opts).unwrap(),
+
+            SYSLOG_CONNECTIONS: register_int_counter_vec!(syslog_connections_opts,&["peer"]).unwrap(),
+            SYSLOG_CONNECTIONS_ACTIVE: register_int_gauge!(syslog_connections_active_opts).unwrap(),
+            SYSLOG_DROPPED_FRAMES: register_int_counter_vec!(syslog_dropped_frames_opts,&["protocol"]).unwrap(),
+
+            UNIX_SOCKET_CONNECTIONS: register_int_counter_vec!(unix_socket_connections_opts,&["event"]).unwrap(),
+            UNIX_SOCKET_CONNECTIONS_ACTIVE: register_int_gauge!(unix_socket_connections_active_opts).unwrap(),
+            UNIX_SOCKET_LISTENING: register_int_gauge_vec!(unix_socket_listening_opts,&["socket"]).unwrap(),
+            UNIX_SOCKET_DATAGRAMS: register_int_counter_vec!(unix_socket_datagrams_opts,&["result"]).unwrap(),
+            UNIX_SOCKET_DROPPED_LINES: register_int_counter_vec!(unix_socket_dropped_lines_opts,&["socket"]).unwrap(),
+
+            INGEST_REQUESTS: register_int_counter_vec!(ingest_requests_opts,&["result"]).unwrap(),
+            INGEST_LINES: register_int_counter_vec!(ingest_lines_opts,&["result"]).unwrap(),
+
+            PIPELINE_QUEUE_DEPTH: register_int_gauge!(pipeline_queue_depth_opts).unwrap(),
+            PIPELINE_DROPPED_LINES: register_int_counter!(pipeline_dropped_lines_opts).unwrap(),
+            PIPELINE_STAGE_SECONDS: register_histogram_vec!(pipeline_stage_histogram_opts,&["stage"]).unwrap(),
+
+            REQUEST_COUNTER: register_int_counter_vec!(request_counter_opts,&request_label_names(&["url", "status"], request_labels, &source_label)).unwrap(),
+            REQUEST_QUERY_COUNTER: register_int_counter_vec!(request_query_counter_opts,&request_label_names(&["operation", "error"], request_labels, &source_label)).unwrap(),
+            QUERY_EXECUTION_TIMES: register_histogram_vec!(query_execution_seconds_histogram_opts,&request_label_names(&["operation", "error"], request_labels, &source_label)).unwrap(),
+            REQUEST_DURATION: register_histogram_vec!(request_duration_histogram_opts,&request_label_names(&["operation", "status"], request_labels, &source_label)).unwrap(),
+            RESPONSE_SIZE: register_histogram_vec!(response_size_histogram_opts,&request_label_names(&["operation", "status"], request_labels, &source_label)).unwrap(),
+            ROOT_FIELD_REQUESTS: register_int_counter_vec!(root_field_requests_opts,&log_label_names(&["operation_type", "root_field", "error"], &source_label)).unwrap(),
+            REQUEST_BATCH_SIZE: register_histogram_vec!(request_batch_size_histogram_opts,&log_label_names(&[], &source_label)).unwrap(),
+
+            GENERATED_SQL_STATEMENTS: register_int_counter_vec!(generated_sql_statements_opts,&log_label_names(&["operation", "root_field"], &source_label)).unwrap(),
+            GENERATED_SQL_SIZE: register_histogram_vec!(generated_sql_size_histogram_opts,&log_label_names(&["operation", "root_field"], &source_label)).unwrap(),
+
+            WEBHOOK_REQUESTS: register_int_counter_vec!(webhook_requests_opts,&log_label_names(&["url", "method", "status", "error"], &source_label)).unwrap(),
+            WEBHOOK_LATENCY: register_histogram_vec!(webhook_latency_histogram_opts,&log_label_names(&["url", "status"], &source_label)).unwrap(),
+
+            EVENT_TRIGGER_DELIVERIES: register_int_counter_vec!(event_trigger_deliveries_opts,&log_label_names(&["trigger_name", "status"], &source_label)).unwrap(),
+            EVENT_TRIGGER_DELIVERY_LATENCY: register_histogram_vec!(event_trigger_delivery_latency_histogram_opts,&log_label_names(&["trigger_name"], &source_label)).unwrap(),
+
+            SCHEDULED_TRIGGER_INVOCATIONS: register_int_counter_vec!(scheduled_trigger_invocations_opts,&log_label_names(&["trigger_name", "status"], &source_label)).unwrap(),
+            SCHEDULED_TRIGGER_INVOCATION_LATENCY: register_histogram_vec!(scheduled_trigger_invocation_latency_histogram_opts,&log_label_names(&["trigger_name"], &source_label)).unwrap(),
+
+            LIVEQUERY_POLLS: register_int_counter_vec!(livequery_polls_opts,&log_label_names(&["kind", "parameterized_query_hash"], &source_label)).unwrap(),
+            LIVEQUERY_COHORTS: register_int_gauge_vec!(livequery_cohorts_opts,&log_label_names(&["kind", "parameterized_query_hash"], &source_label)).unwrap(),
+            LIVEQUERY_POLL_TIME: register_histogram_vec!(livequery_poll_time_histogram_opts,&log_label_names(&["kind", "parameterized_query_hash"], &source_label)).unwrap(),
+            LIVEQUERY_SNAPSHOT_TIME: register_histogram_vec!(livequery_snapshot_time_histogram_opts,&log_label_names(&["kind", "parameterized_query_hash"], &source_label)).unwrap(),
+            LIVEQUERY_DB_EXECUTION_TIME: register_histogram_vec!(livequery_db_execution_time_histogram_opts,&log_label_names(&["kind", "parameterized_query_hash"], &source_label)).unwrap(),
+            LIVEQUERY_PUSH_TIME: register_histogram_vec!(livequery_push_time_histogram_opts,&log_label_names(&["kind", "parameterized_query_hash"], &source_label)).unwrap(),
+
+            CARDINALITY_FOLDED: register_int_counter_vec!(cardinality_folded_opts,&["metric", "label"]).unwrap(),
+
+            rules: Rules::default(),
+            url_normalizer: UrlNormalizer::default(),
+
+            source_label_enabled: source_label.is_some(),
+            request_labels,
+            cardinality_guard: CardinalityGuard::new(cardinality_limits),
+            last_timestamps: Arc::new(RwLock::new(HashMap::new())),
+        };
+
+        // without a source label these metrics have no labels at all, initialize them so
+        // they are exported before the first log line arrives
+        if !telemetry.source_label_enabled {
+            telemetry.LOG_LINES_COUNTER_TOTAL.with_label_values(&[]);
+            telemetry.ACTIVE_WEBSOCKET.with_label_values(&[]);
+            telemetry.ACTIVE_WEBSOCKET_OPERATIONS.with_label_values(&[]);
+        }
+
+        telemetry
 
-            LOG_LINES_COUNTER_TOTAL: register_int_counter!(log_lines_counter_total_opts).unwrap(),
-            LOG_LINES_COUNTER: register_int_counter_vec!(log_lines_counter_opts,&["logtype"]).unwrap(),
+    }
+}
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::OnceLock;
+
+    /// The metrics are registered in the global registry, so all tests share one telemetry.
+    /// The source label keeps the tests apart, every test processes its lines with its own source.
+    pub(crate) fn telemetry() -> Telemetry {
+        static TELEMETRY: OnceLock<Telemetry> = OnceLock::new();
+        TELEMETRY.get_or_init(|| Telemetry::new(
+            HashMap::new(),
+            vec![],
+            vec![],
+            vec![],
+            RequestLabels { role: true, client: true },
+            CardinalityLimits::default(),
+            Some(String::from("source")),
+        )).clone()
+    }
 
-            REQUEST_COUNTER: register_int_counter_vec!(request_counter_opts,&["url", "status"]).unwrap(),
-            REQUEST_QUERY_COUNTER: register_int_counter_vec!(request_query_counter_opts,&["operation", "error"]).unwrap(),
-            QUERY_EXECUTION_TIMES: register_histogram_vec!(query_execution_seconds_histogram_opts,&["operation", "error"]).unwrap()
+    #[test]
+    fn keeps_the_latest_timestamp_of_concurrent_workers() {
+        let metric_obj = telemetry();
+        let workers: Vec<_> = (0..8)
+            .map(|worker| {
+                let metric_obj = metric_obj.clone();
+                std::thread::spawn(move || {
+                    for line in 0..1000 {
+                        metric_obj.set_last_timestamp(&["http-log", "concurrent-timestamps"], line * 8 + worker);
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
         }
+        assert_eq!(metric_obj.LOG_LAST_TIMESTAMP.with_label_values(&["http-log", "concurrent-timestamps"]).get(), 7.999);
 
+        metric_obj.set_last_timestamp(&["http-log", "concurrent-timestamps"], 5000);
+        assert_eq!(metric_obj.LOG_LAST_TIMESTAMP.with_label_values(&["http-log", "concurrent-timestamps"]).get(), 7.999);
     }
-}
\ No newline at end of file
+}
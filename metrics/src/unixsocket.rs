@@ -0,0 +1,206 @@
+use log::{debug, info, warn};
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::fs::FileTypeExt;
+use std::sync::Arc;
+use tokio::{
+    io::BufReader,
+    net::{UnixDatagram, UnixListener, UnixStream},
+    sync::watch,
+};
+
+use crate::pipeline::Pipeline;
+use crate::syslog::{read_line_frame, Frame, MAX_DATAGRAM_SIZE, MAX_FRAME_SIZE};
+use crate::{Configuration, Telemetry};
+
+/// Source label value of lines received via a unix socket
+const UNIX_SOCKET_SOURCE: &str = "unix-socket";
+
+pub(crate) async fn run_unix_socket_receiver(cfg: &Configuration, metric_obj: &Telemetry, pipeline: &Pipeline, termination_rx: watch::Receiver<()>) -> Result<()> {
+    tokio::try_join!(
+        async {
+            match &cfg.unix_socket {
+                Some(path) => listen_stream(path, metric_obj, pipeline, termination_rx.clone()).await,
+                None => Ok(()),
+            }
+        },
+        async {
+            match &cfg.unix_datagram_socket {
+                Some(path) => listen_datagram(path, metric_obj, pipeline, termination_rx.clone()).await,
+                None => Ok(()),
+            }
+        }
+    )?;
+    Ok(())
+}
+
+/// The adapter owns the socket file, a socket left over from a previous run is replaced.
+/// Any other file at the path is an error, so a wrong path can't remove e.g. a log file.
+fn remove_stale_socket(path: &str) -> Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            debug!("Removing stale unix socket {}", path);
+            std::fs::remove_file(path)
+        }
+        Ok(_) => Err(Error::new(ErrorKind::AlreadyExists, format!("{} exists and is not a unix socket", path))),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+fn remove_socket(path: &str) {
+    if let Err(e) = std::fs::remove_file(path) {
+        warn!("Failed to remove unix socket {}: {}", path, e);
+    }
+}
+
+async fn listen_stream(path: &str, metric_obj: &Telemetry, pipeline: &Pipeline, mut termination_rx: watch::Receiver<()>) -> Result<()> {
+    remove_stale_socket(path)?;
+    let listener = UnixListener::bind(path)?;
+    info!("Listening for hasura logs on unix socket {}", path);
+    metric_obj.UNIX_SOCKET_LISTENING.with_label_values(&["stream"]).set(1);
+
+    loop {
+        tokio::select! {
+            biased;
+            _ = termination_rx.changed() => {
+                remove_socket(path);
+                metric_obj.UNIX_SOCKET_LISTENING.with_label_values(&["stream"]).set(0);
+                return Ok(());
+            }
+
+            result = listener.accept() => {
+                match result {
+                    Ok((stream, _)) => {
+                        tokio::spawn(handle_connection(stream, metric_obj.clone(), pipeline.clone(), termination_rx.clone()));
+                    }
+                    Err(e) => {
+                        warn!("Failed to accept unix socket connection: {}", e);
+                        metric_obj.UNIX_SOCKET_CONNECTIONS.with_label_values(&["failed"]).inc();
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn handle_connection(stream: UnixStream, metric_obj: Telemetry, pipeline: Pipeline, mut termination_rx: watch::Receiver<()>) {
+    debug!("Accepted unix socket connection");
+    metric_obj.UNIX_SOCKET_CONNECTIONS.with_label_values(&["opened"]).inc();
+    metric_obj.UNIX_SOCKET_CONNECTIONS_ACTIVE.inc();
+
+    let source: Arc<str> = Arc::from(UNIX_SOCKET_SOURCE);
+    let mut reader = BufReader::new(stream);
+    loop {
+        tokio::select! {
+            biased;
+            _ = termination_rx.changed() => break,
+
+            frame = read_line_frame(&mut reader) => {
+                match frame {
+                    Ok(Some(Frame::Message(bytes))) => match String::from_utf8(bytes) {
+                        Ok(mut line) => {
+                            debug!("Reading line from unix socket");
+                            line.truncate(line.trim_end_matches(['\r', '\n']).len());
+                            pipeline.submit(line, &source).await;
+                        }
+                        Err(e) => {
+                            warn!("Dropping invalid unix socket line: {}", e);
+                            metric_obj.UNIX_SOCKET_DROPPED_LINES.with_label_values(&["stream"]).inc();
+                        }
+                    },
+                    Ok(Some(Frame::Invalid)) => {
+                        warn!("Dropping unix socket line larger than {} bytes", MAX_FRAME_SIZE);
+                        metric_obj.UNIX_SOCKET_DROPPED_LINES.with_label_values(&["stream"]).inc();
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        warn!("Error reading unix socket connection: {}", e);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    debug!("Unix socket connection closed");
+    metric_obj.UNIX_SOCKET_CONNECTIONS.with_label_values(&["closed"]).inc();
+    metric_obj.UNIX_SOCKET_CONNECTIONS_ACTIVE.dec();
+}
+
+async fn listen_datagram(path: &str, metric_obj: &Telemetry, pipeline: &Pipeline, mut termination_rx: watch::Receiver<()>) -> Result<()> {
+    remove_stale_socket(path)?;
+    let socket = UnixDatagram::bind(path)?;
+    info!("Listening for hasura logs on unix datagram socket {}", path);
+    metric_obj.UNIX_SOCKET_LISTENING.with_label_values(&["datagram"]).set(1);
+
+    let source: Arc<str> = Arc::from(UNIX_SOCKET_SOURCE);
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+    loop {
+        tokio::select! {
+            biased;
+            _ = termination_rx.changed() => {
+                remove_socket(path);
+                metric_obj.UNIX_SOCKET_LISTENING.with_label_values(&["datagram"]).set(0);
+                return Ok(());
+            }
+
+            result = socket.recv(&mut buf) => {
+                match result {
+                    Ok(len) => {
+                        metric_obj.UNIX_SOCKET_DATAGRAMS.with_label_values(&["received"]).inc();
+                        match std::str::from_utf8(&buf[..len]) {
+                            // a datagram may carry multiple lines
+                            Ok(datagram) => {
+                                for line in datagram.lines().filter(|line| !line.trim().is_empty()) {
+                                    pipeline.submit(line.to_string(), &source).await;
+                                }
+                            }
+                            Err(e) => {
+                                warn!("Dropping invalid unix socket datagram: {}", e);
+                                metric_obj.UNIX_SOCKET_DROPPED_LINES.with_label_values(&["datagram"]).inc();
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Failed to receive unix socket datagram: {}", e);
+                        metric_obj.UNIX_SOCKET_DATAGRAMS.with_label_values(&["failed"]).inc();
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("metrics-unixsocket-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn removes_stale_socket() {
+        let path = temp_path("stale.sock");
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        remove_stale_socket(&path).unwrap();
+        assert!(std::fs::symlink_metadata(&path).is_err());
+    }
+
+    #[test]
+    fn keeps_other_files() {
+        let path = temp_path("hasura.log");
+        std::fs::write(&path, "log").unwrap();
+        let err = remove_stale_socket(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "log");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ignores_missing_path() {
+        remove_stale_socket(&temp_path("missing.sock")).unwrap();
+    }
+}
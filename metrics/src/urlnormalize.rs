@@ -0,0 +1,206 @@
+use std::sync::{Arc, RwLock};
+
+use regex::Regex;
+use serde_json::{Map, Value};
+
+use crate::telemetry::OTHER_LABEL_VALUE;
+
+/// Path prefix of the RESTified endpoints
+const REST_PREFIX: &str = "/api/rest/";
+
+/// Path prefix of the console, all console paths are reported as the prefix
+const CONSOLE_PREFIX: &str = "/console";
+
+/// Paths of the hasura API, which are reported as they are
+const HASURA_PATHS: &[&str] = &[
+    "/v1/graphql",
+    "/v1/graphql/explain",
+    "/v1alpha1/graphql",
+    "/v1beta1/relay",
+    "/v1/metadata",
+    "/v1/query",
+    "/v2/query",
+    "/v1/version",
+    "/v1/config",
+    "/v1alpha1/config",
+    "/v1alpha1/pg_dump",
+    "/healthz",
+    "/hasura/healthz",
+];
+
+/// Rewrite rule of the url label, the replacement can refer to the groups of the pattern (e.g. `$1`)
+#[derive(Clone, Debug)]
+pub struct UrlRewrite {
+    pub pattern: Regex,
+    pub replacement: String,
+}
+
+pub fn url_rewrite_parser(input: &str) -> Result<UrlRewrite, String> {
+    let (pattern, replacement) = input.rsplit_once('=')
+        .ok_or_else(|| format!("invalid REGEX=replacement: no `=` found in `{}`", input))?;
+    let pattern = Regex::new(pattern).map_err(|e| format!("invalid url rewrite pattern `{}`: {}", pattern, e))?;
+    Ok(UrlRewrite {
+        pattern,
+        replacement: replacement.to_string(),
+    })
+}
+
+/// Url template of a RESTified endpoint, e.g. `users/:id`
+#[derive(Debug)]
+struct RestTemplate {
+    segments: Vec<String>,
+    label: String,
+}
+
+impl RestTemplate {
+    fn new(url: &str) -> RestTemplate {
+        let url = url.trim_matches('/');
+        RestTemplate {
+            segments: url.split('/').map(String::from).collect(),
+            label: format!("{}{}", REST_PREFIX, url),
+        }
+    }
+
+    fn matches(&self, segments: &[&str]) -> bool {
+        self.segments.len() == segments.len() && self.segments.iter().zip(segments).all(|(template, segment)| {
+            template == segment || (template.starts_with(':') && !segment.is_empty())
+        })
+    }
+
+    fn static_segments(&self) -> usize {
+        self.segments.iter().filter(|segment| !segment.starts_with(':')).count()
+    }
+}
+
+/// Turns the url of a request into a label value with a bounded number of values. The query
+/// string is stripped, the rewrite rules are applied in order and RESTified endpoints are
+/// reported with their template. Other paths, which are not part of the hasura API, are
+/// reported as `__other__`.
+#[derive(Clone, Debug, Default)]
+pub struct UrlNormalizer {
+    rewrites: Arc<Vec<UrlRewrite>>,
+    /// Templates of the RESTified endpoints, updated from the metadata by the collector
+    rest_templates: Arc<RwLock<Vec<RestTemplate>>>,
+}
+
+impl UrlNormalizer {
+    pub fn new(rewrites: Vec<UrlRewrite>) -> UrlNormalizer {
+        UrlNormalizer {
+            rewrites: Arc::new(rewrites),
+            rest_templates: Arc::new(RwLock::new(Vec::new())),
+        }
+    }
+
+    /// Replaces the templates with the RESTified endpoints of the exported metadata, returns their number
+    pub fn set_rest_endpoints(&self, metadata: &Map<String, Value>) -> usize {
+        let templates: Vec<RestTemplate> = metadata.get("rest_endpoints")
+            .and_then(|endpoints| endpoints.as_array())
+            .map(|endpoints| endpoints.iter()
+                .filter_map(|endpoint| endpoint.get("url").and_then(|url| url.as_str()))
+                .map(RestTemplate::new)
+                .collect())
+            .unwrap_or_default();
+        let count = templates.len();
+        *self.rest_templates.write().unwrap() = templates;
+        count
+    }
+
+    pub fn normalize(&self, url: &str) -> String {
+        let path = url.split(['?', '#']).next().unwrap_or("");
+
+        if let Some(rewrite) = self.rewrites.iter().find(|rewrite| rewrite.pattern.is_match(path)) {
+            return rewrite.pattern.replace(path, rewrite.replacement.as_str()).into_owned();
+        }
+
+        let templates = self.rest_templates.read().unwrap();
+        if let Some(endpoint) = path.strip_prefix(REST_PREFIX) {
+            let segments: Vec<&str> = endpoint.trim_end_matches('/').split('/').collect();
+            // a static segment is more specific than a parameter, e.g. `users/me` wins over `users/:id`
+            let template = templates.iter()
+                .filter(|template| template.matches(&segments))
+                .max_by_key(|template| template.static_segments());
+            if let Some(template) = template {
+                return template.label.clone();
+            }
+        } else if HASURA_PATHS.contains(&path) {
+            return path.to_string();
+        } else if path == CONSOLE_PREFIX || path.starts_with("/console/") {
+            return CONSOLE_PREFIX.to_string();
+        }
+
+        OTHER_LABEL_VALUE.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn normalizer(rewrites: &[&str], rest_endpoints: Option<Value>) -> UrlNormalizer {
+        let normalizer = UrlNormalizer::new(rewrites.iter().map(|rewrite| url_rewrite_parser(rewrite).unwrap()).collect());
+        if let Some(rest_endpoints) = rest_endpoints {
+            let metadata = json!({ "rest_endpoints": rest_endpoints });
+            normalizer.set_rest_endpoints(metadata.as_object().unwrap());
+        }
+        normalizer
+    }
+
+    #[test]
+    fn strips_query_string_and_fragment() {
+        let normalizer = normalizer(&[], Some(json!([])));
+        assert_eq!(normalizer.normalize("/v1/graphql?query=1"), "/v1/graphql");
+        assert_eq!(normalizer.normalize("/v1/metadata#x"), "/v1/metadata");
+    }
+
+    #[test]
+    fn matches_rest_templates() {
+        let normalizer = normalizer(&[], Some(json!([
+            { "name": "user", "url": "users/:id" },
+            { "name": "me", "url": "users/me" },
+            { "name": "post", "url": "/users/:id/posts/:post/" },
+        ])));
+        assert_eq!(normalizer.normalize("/api/rest/users/123?x=1"), "/api/rest/users/:id");
+        assert_eq!(normalizer.normalize("/api/rest/users/123/"), "/api/rest/users/:id");
+        assert_eq!(normalizer.normalize("/api/rest/users/me"), "/api/rest/users/me");
+        assert_eq!(normalizer.normalize("/api/rest/users/1/posts/2"), "/api/rest/users/:id/posts/:post");
+        assert_eq!(normalizer.normalize("/api/rest/users//posts/2"), OTHER_LABEL_VALUE);
+        assert_eq!(normalizer.normalize("/api/rest/unknown"), OTHER_LABEL_VALUE);
+    }
+
+    #[test]
+    fn folds_unknown_paths() {
+        let normalizer = normalizer(&[], Some(json!([])));
+        assert_eq!(normalizer.normalize("/wp-admin"), OTHER_LABEL_VALUE);
+        assert_eq!(normalizer.normalize("/console/assets/main.js"), "/console");
+        assert_eq!(normalizer.normalize("/healthz"), "/healthz");
+    }
+
+    #[test]
+    fn folds_unknown_paths_without_templates() {
+        let normalizer = normalizer(&[], None);
+        assert_eq!(normalizer.normalize("/api/rest/users/123?x=1"), OTHER_LABEL_VALUE);
+        assert_eq!(normalizer.normalize("/wp-admin"), OTHER_LABEL_VALUE);
+        assert_eq!(normalizer.normalize("/v1/graphql"), "/v1/graphql");
+    }
+
+    #[test]
+    fn keeps_paths_with_rewrite() {
+        let normalizer = normalizer(&["^(/api/rest/.*)$=$1"], None);
+        assert_eq!(normalizer.normalize("/api/rest/users/123?x=1"), "/api/rest/users/123");
+    }
+
+    #[test]
+    fn applies_first_matching_rewrite() {
+        let normalizer = normalizer(&["^/custom/[0-9]+$=/custom/:id", "^/custom/(.*)$=/c/$1", "^/v1/(.*)$=/v1/all"], Some(json!([])));
+        assert_eq!(normalizer.normalize("/custom/42?x=1"), "/custom/:id");
+        assert_eq!(normalizer.normalize("/custom/abc"), "/c/abc");
+        assert_eq!(normalizer.normalize("/v1/graphql"), "/v1/all");
+    }
+
+    #[test]
+    fn rejects_invalid_rewrites() {
+        assert!(url_rewrite_parser("no-separator").is_err());
+        assert!(url_rewrite_parser("(unclosed=x").is_err());
+    }
+}